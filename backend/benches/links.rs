@@ -0,0 +1,74 @@
+use backend::links::{BuiltPattern, apply_patterns, builtin_patterns, prefilter::Prefilter};
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use fancy_regex::Regex;
+use std::sync::Arc;
+
+/// Roughly what a busy channel looks like, most messages have no links at all
+const CORPUS: &[&str] = &[
+    "gm",
+    "anyone up for some osu later?",
+    "lmao",
+    "that map is actually insane, the second kiai is unreadable",
+    "I can't believe they nerfed it again ||the ending was better before||",
+    "https://x.com/testaccount/status/1814183041708990884",
+    "look at this https://www.reddit.com/r/osugame/comments/abc123/some_title/ lol",
+    "`https://x.com/testaccount/status/1814183041708990884` is how you'd write it",
+    "https://www.pixiv.net/en/artworks/117847824 so good",
+    "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+    "https://github.com/Sigi3012/Midnight/pulls",
+    "brb",
+    "ok so the plan is: farm for an hour, then multi, then sleep",
+    "https://www.instagram.com/reel/C9xYz_1-AbC/?igsh=abcdef",
+    "https://www.tiktok.com/@testaccount/video/7401234567890123456 and https://x.com/other/status/1",
+    "no way 💀",
+];
+
+/// Guild rules piling up next to the builtin ones
+fn grown_patterns(extra: usize) -> Vec<Arc<BuiltPattern>> {
+    let mut patterns = builtin_patterns();
+    patterns.extend((0..extra).map(|i| {
+        Arc::new(BuiltPattern::new(
+            format!("custom{}", i),
+            Regex::new(&format!(r"https://(?:www\.)?site{}\.example/(\w+)", i))
+                .expect("Regex should compile"),
+            format!("[Site](https://mirror{}.example/$1)", i),
+            vec![],
+        ))
+    }));
+    patterns
+}
+
+fn run(patterns: &[Arc<BuiltPattern>], prefilter: &Prefilter) {
+    for message in CORPUS {
+        let _ = black_box(apply_patterns(
+            black_box(message),
+            patterns,
+            prefilter,
+            true,
+        ));
+    }
+}
+
+fn bench_prefilter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fix_corpus");
+    for extra in [0, 50] {
+        let patterns = grown_patterns(extra);
+        let prefilter = Prefilter::new(&patterns);
+        let unfiltered = Prefilter::none();
+
+        group.bench_with_input(
+            BenchmarkId::new("prefiltered", patterns.len()),
+            &prefilter,
+            |b, prefilter| b.iter(|| run(&patterns, prefilter)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("unfiltered", patterns.len()),
+            &unfiltered,
+            |b, prefilter| b.iter(|| run(&patterns, prefilter)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_prefilter);
+criterion_main!(benches);
@@ -97,12 +97,12 @@ mod tests {
 
     #[test]
     fn test_derserialization() {
-        let mock: Vec<String> = vec![r#"
+        let mock: Vec<String> = [r#"
             [{"breeds":[{"weight":{"imperial":"7 - 15","metric":"3 - 7"},"id":"chau","name":"Chausie","temperament":"Affectionate, Intelligent, Playful, Social","origin":"Egypt","country_codes":"EG","country_code":"EG","description":"For those owners who desire a feline capable of evoking the great outdoors, the strikingly beautiful Chausie retains a bit of the wild in its appearance but has the house manners of our friendly, familiar moggies. Very playful, this cat needs a large amount of space to be able to fully embrace its hunting instincts.","life_span":"12 - 14","indoor":0,"alt_names":"Nile Cat","adaptability":5,"affection_level":5,"child_friendly":4,"dog_friendly":5,"energy_level":4,"grooming":3,"health_issues":1,"intelligence":5,"shedding_level":3,"social_needs":3,"stranger_friendly":4,"vocalisation":1,"experimental":1,"hairless":0,"natural":0,"rare":0,"rex":0,"suppressed_tail":0,"short_legs":0,"wikipedia_url":"https://en.wikipedia.org/wiki/Chausie","hypoallergenic":0,"reference_image_id":"vJ3lEYgXr"}],"id":"r0s90j0I8","url":"https://cdn2.thecatapi.com/images/r0s90j0I8.jpg","width":2093,"height":2105}]
         "#,
         r#"
         [{"breeds":[],"id":"2ls","url":"https://cdn2.thecatapi.com/images/2ls.jpg","width":500,"height":333}]
-        "#].iter_mut().map(|s| s.to_string()).collect();
+        "#].iter().map(|s| s.to_string()).collect();
         let deserialized = ResponseJson::from_strings(mock);
         let expected_long = ResponseJson {
             breeds: Some(Breed {
@@ -153,7 +153,7 @@ pub async fn fetch_beatmaps(ids: Vec<i32>) -> Result<BeatmapsetVec> {
     let headers = build_headers().await?;
     let client = REQWEST_CLIENT.clone();
 
-    let beatmaps: BeatmapsetVec = stream::iter(ids.into_iter())
+    let beatmaps: BeatmapsetVec = stream::iter(ids)
         .map(|id| fetch_beatmap(id, &client, headers.clone()))
         .buffer_unordered(MAX_CONCURRENT_REQUESTS)
         .filter_map(|result| futures::future::ready(result.ok()))
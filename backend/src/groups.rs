@@ -166,7 +166,7 @@ async fn process(diff: &Diff, group: OsuGroup) -> Result<Vec<CreateEmbed>> {
                 .author(
                     CreateEmbedAuthor::new(&user.username)
                         .icon_url(&user.avatar_url)
-                        .url(format!("https://osu.ppy.sh/users/{}", &user.id)),
+                        .url(format!("https://osu.ppy.sh/users/{}", user.id)),
                 )
                 .title(format!("Added to `{group}`"))
                 .description(description)
@@ -182,7 +182,7 @@ async fn process(diff: &Diff, group: OsuGroup) -> Result<Vec<CreateEmbed>> {
                 .author(
                     CreateEmbedAuthor::new(&user.username)
                         .icon_url(&user.avatar_url)
-                        .url(format!("https://osu.ppy.sh/users/{}", &user.id)),
+                        .url(format!("https://osu.ppy.sh/users/{}", user.id)),
                 )
                 .title(format!("Removed from `{group}`"))
                 .colour(Colour::new(0xff3737))
@@ -212,7 +212,7 @@ async fn process(diff: &Diff, group: OsuGroup) -> Result<Vec<CreateEmbed>> {
                 .author(
                     CreateEmbedAuthor::new(&user.username)
                         .icon_url(&user.avatar_url)
-                        .url(format!("https://osu.ppy.sh/users/{}", &user.id)),
+                        .url(format!("https://osu.ppy.sh/users/{}", user.id)),
                 )
                 .title(format!("Updated gamemodes in `{group}`"))
                 .description(description)
@@ -0,0 +1,398 @@
+use super::{
+    BuiltPattern,
+    markdown::{self, Token},
+    tracking::{self, TRACKING_FILTER},
+};
+use futures::future::join_all;
+use log::{debug, warn};
+use reqwest::{Client, header::LOCATION, redirect::Policy};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Short link domains and the domain their links lead to, `None` for general shorteners
+#[cfg(not(test))]
+const SHORT_LINK_DOMAINS: [(&str, Option<&str>); 9] = [
+    ("vm.tiktok.com", Some("tiktok.com")),
+    ("vt.tiktok.com", Some("tiktok.com")),
+    ("t.co", None),
+    ("youtu.be", Some("youtube.com")),
+    ("redd.it", Some("reddit.com")),
+    ("pin.it", Some("pinterest.com")),
+    ("bit.ly", None),
+    ("b23.tv", Some("bilibili.com")),
+    ("x.gd", None),
+];
+const EXPAND_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_REDIRECTS: usize = 5;
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+const CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    pub static ref SHORT_LINKS: ShortLinkExpander =
+        ShortLinkExpander::new(short_link_domains(), EXPAND_TIMEOUT);
+}
+
+#[cfg(not(test))]
+fn short_link_domains() -> Vec<(String, Option<String>)> {
+    SHORT_LINK_DOMAINS
+        .iter()
+        .map(|(domain, target)| (domain.to_string(), target.map(str::to_string)))
+        .collect()
+}
+
+/// Unit tests never reach the real services, expanding is tested against a local server
+#[cfg(test)]
+fn short_link_domains() -> Vec<(String, Option<String>)> {
+    Vec::new()
+}
+
+/// Resolves short links to the url they redirect to
+pub struct ShortLinkExpander {
+    client: Client,
+    /// Short link domains mapped to the domain their links lead to, if it is known
+    domains: HashMap<String, Option<String>>,
+    // `None` is cached as well so dead links aren't requested on every message
+    cache: Mutex<HashMap<String, (Instant, Option<String>)>>,
+}
+
+impl ShortLinkExpander {
+    pub fn new(domains: Vec<(String, Option<String>)>, timeout: Duration) -> Self {
+        Self {
+            client: Client::builder()
+                .redirect(Policy::none())
+                .timeout(timeout)
+                .build()
+                .expect("Client should build with a plain config"),
+            domains: domains.into_iter().collect(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_short_link(&self, url: &Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| self.domains.contains_key(host))
+    }
+
+    /// Whether expanding `link` could let one of `patterns` rewrite it
+    ///
+    /// Short links a rule already rewrites as they are stay as they are, links from a known
+    /// short link domain are only expanded when a rule mentions the domain they lead to
+    fn worth_expanding(
+        &self,
+        link: &str,
+        patterns: &[Arc<BuiltPattern>],
+    ) -> Result<bool, Box<fancy_regex::Error>> {
+        let Some(target) = Url::parse(link).ok().and_then(|url| {
+            url.host_str()
+                .and_then(|host| self.domains.get(host))
+                .cloned()
+        }) else {
+            return Ok(false);
+        };
+        for built in patterns {
+            if built.pattern.is_match(link)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(match target {
+            Some(domain) => {
+                let escaped = domain.replace('.', "\\.");
+                patterns
+                    .iter()
+                    .any(|built| built.pattern.as_str().contains(&escaped))
+            }
+            None => !patterns.is_empty(),
+        })
+    }
+
+    /// Follows redirects from `link` until it leaves the short link domains
+    ///
+    /// Returns `None` for links that aren't short links or can't be resolved
+    pub async fn expand(&self, link: &str) -> Option<String> {
+        let mut current = Url::parse(link).ok()?;
+        if !self.is_short_link(&current) {
+            return None;
+        }
+
+        if let Some((cached_at, expanded)) = self.cache.lock().await.get(link) {
+            if cached_at.elapsed() < CACHE_TTL {
+                return expanded.clone();
+            }
+        }
+
+        let mut expanded = None;
+        for _ in 0..MAX_REDIRECTS {
+            let response = match self.client.get(current.clone()).send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Failed to expand {}, {}", link, e);
+                    break;
+                }
+            };
+            let Some(location) = response
+                .status()
+                .is_redirection()
+                .then(|| response.headers().get(LOCATION))
+                .flatten()
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| current.join(location).ok())
+            else {
+                break;
+            };
+
+            if !self.is_short_link(&location) {
+                expanded = Some(location.to_string());
+                break;
+            }
+            current = location;
+        }
+        debug!("Expanded {} to {:?}", link, expanded);
+
+        let mut cache = self.cache.lock().await;
+        if cache.len() >= CACHE_CAPACITY {
+            cache.retain(|_, (cached_at, _)| cached_at.elapsed() < CACHE_TTL);
+            if cache.len() >= CACHE_CAPACITY {
+                cache.clear();
+            }
+        }
+        cache.insert(link.to_owned(), (Instant::now(), expanded.clone()));
+
+        expanded
+    }
+
+    /// Replaces short links in `content` with where they lead, but only when a rule
+    /// is going to rewrite the expanded link
+    ///
+    /// Every short link is requested at the same time
+    pub async fn expand_links(
+        &self,
+        content: &str,
+        patterns: &[Arc<BuiltPattern>],
+    ) -> Result<String, Box<fancy_regex::Error>> {
+        let tokens = markdown::tokenize(content);
+
+        let mut links = HashSet::new();
+        for token in &tokens {
+            let Token::Text(text) = token else {
+                continue;
+            };
+            for range in tracking::find_urls(text) {
+                let link = &text[range];
+                if self.worth_expanding(link, patterns)? {
+                    links.insert(link);
+                }
+            }
+        }
+        if links.is_empty() {
+            return Ok(content.to_string());
+        }
+        let expanded: HashMap<&str, String> = join_all(
+            links
+                .into_iter()
+                .map(|link| async move { (link, self.expand(link).await) }),
+        )
+        .await
+        .into_iter()
+        .filter_map(|(link, expanded)| Some((link, expanded?)))
+        .collect();
+
+        let mut result = String::with_capacity(content.len());
+        for token in tokens {
+            let Token::Text(text) = token else {
+                result.push_str(token.as_str());
+                continue;
+            };
+
+            let mut last = 0;
+            for range in tracking::find_urls(text) {
+                let Some(expanded) = expanded.get(&text[range.clone()]) else {
+                    continue;
+                };
+                let expanded = TRACKING_FILTER
+                    .clean(expanded)
+                    .unwrap_or_else(|| expanded.clone());
+
+                let mut rewritable = false;
+                for built in patterns.iter() {
+                    if built.pattern.is_match(&expanded)? {
+                        rewritable = true;
+                        break;
+                    }
+                }
+                if rewritable {
+                    result.push_str(&text[last..range.start]);
+                    result.push_str(&expanded);
+                    last = range.end;
+                }
+            }
+            result.push_str(&text[last..]);
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fancy_regex::Regex;
+    use pretty_assertions::assert_eq;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves `302`s from `/short` to `/hop` to `target`, counting every request it gets
+    async fn redirect_server(target: &'static str) -> (String, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(Mutex::new(0));
+
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0; 1024];
+                let read = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                *counter.lock().await += 1;
+
+                let response = if request.starts_with("GET /short ") {
+                    "HTTP/1.1 302 Found\r\nLocation: /hop\r\nContent-Length: 0\r\n\r\n".to_string()
+                } else if request.starts_with("GET /hop ") {
+                    format!(
+                        "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                        target
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (address, hits)
+    }
+
+    fn expander() -> ShortLinkExpander {
+        ShortLinkExpander::new(
+            vec![("127.0.0.1".to_string(), None)],
+            Duration::from_secs(1),
+        )
+    }
+
+    fn tiktok_pattern() -> Vec<Arc<BuiltPattern>> {
+        vec![Arc::new(BuiltPattern::new(
+            "tiktok".to_string(),
+            Regex::new(r"https://www\.tiktok\.com/@([^/]+)/video/(\d+)").unwrap(),
+            "fixed".to_string(),
+            vec![],
+        ))]
+    }
+
+    #[tokio::test]
+    async fn test_follows_redirects() {
+        let (address, _) =
+            redirect_server("https://www.tiktok.com/@user/video/123?_r=1&lang=en").await;
+        let expanded = expander().expand(&format!("{}/short", address)).await;
+        assert_eq!(
+            expanded.as_deref(),
+            Some("https://www.tiktok.com/@user/video/123?_r=1&lang=en")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_caches_results() {
+        let (address, hits) = redirect_server("https://example.com/").await;
+        let expander = expander();
+        let link = format!("{}/short", address);
+
+        expander.expand(&link).await;
+        expander.expand(&link).await;
+        assert_eq!(*hits.lock().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unresolvable_links() {
+        let (address, _) = redirect_server("https://example.com/").await;
+        let expander = expander();
+        assert_eq!(expander.expand(&format!("{}/missing", address)).await, None);
+        assert_eq!(expander.expand("https://example.com/short").await, None);
+        // Nothing is listening on port 9
+        assert_eq!(expander.expand("http://127.0.0.1:9/short").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_only_expands_rewritable_links() {
+        let (address, _) = redirect_server("https://www.tiktok.com/@user/video/123?_r=1").await;
+        let patterns = tiktok_pattern();
+        let content = format!("{0}/short `{0}/short`", address);
+
+        let expanded = expander().expand_links(&content, &patterns).await.unwrap();
+        assert_eq!(
+            expanded,
+            format!("https://www.tiktok.com/@user/video/123 `{}/short`", address)
+        );
+        let untouched = expander().expand_links(&content, &[]).await.unwrap();
+        assert_eq!(untouched, content);
+    }
+
+    #[tokio::test]
+    async fn test_skips_links_no_rule_wants() {
+        let (address, hits) = redirect_server("https://www.tiktok.com/@user/video/123").await;
+        let content = format!("{0}/short and {0}/hop", address);
+
+        // Without rules nothing is requested
+        assert_eq!(
+            expander().expand_links(&content, &[]).await.unwrap(),
+            content
+        );
+        // The links lead to a domain no rule mentions
+        let elsewhere = ShortLinkExpander::new(
+            vec![("127.0.0.1".to_string(), Some("pixiv.net".to_string()))],
+            Duration::from_secs(1),
+        );
+        let patterns = tiktok_pattern();
+        assert_eq!(
+            elsewhere.expand_links(&content, &patterns).await.unwrap(),
+            content
+        );
+        // A rule already rewrites the short link itself
+        let own = vec![Arc::new(BuiltPattern::new(
+            "local".to_string(),
+            Regex::new(r"http://127\.0\.0\.1:\d+/(short|hop)").unwrap(),
+            "fixed".to_string(),
+            vec![],
+        ))];
+        assert_eq!(
+            expander().expand_links(&content, &own).await.unwrap(),
+            content
+        );
+        assert_eq!(*hits.lock().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expands_known_targets() {
+        let (address, hits) = redirect_server("https://www.tiktok.com/@user/video/123").await;
+        let expander = ShortLinkExpander::new(
+            vec![("127.0.0.1".to_string(), Some("tiktok.com".to_string()))],
+            Duration::from_secs(1),
+        );
+        let content = format!("{0}/short {0}/short", address);
+
+        assert_eq!(
+            expander
+                .expand_links(&content, &tiktok_pattern())
+                .await
+                .unwrap(),
+            "https://www.tiktok.com/@user/video/123 https://www.tiktok.com/@user/video/123"
+        );
+        // The same link twice is only followed once, both hops
+        assert_eq!(*hits.lock().await, 2);
+    }
+}
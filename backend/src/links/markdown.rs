@@ -0,0 +1,238 @@
+//! Just enough of Discord's markdown to know where links should be left alone
+
+const FENCE: &str = "```";
+const SPOILER: &str = "||";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Token<'a> {
+    /// Plain text, the only place links get rewritten
+    Text(&'a str),
+    /// Inline code or a fenced code block, backticks included
+    Code(&'a str),
+    /// A link wrapped in `<>` so Discord doesn't embed it, brackets included
+    SuppressedLink(&'a str),
+    /// `||`, opening or closing a spoiler
+    Spoiler,
+}
+
+impl Token<'_> {
+    /// The exact source text of the token
+    pub fn as_str(&self) -> &str {
+        match self {
+            Token::Text(s) | Token::Code(s) | Token::SuppressedLink(s) => s,
+            Token::Spoiler => SPOILER,
+        }
+    }
+}
+
+/// Splits `content` into tokens, concatenating every token gives back `content`
+///
+/// Unterminated code spans and brackets are treated as plain text like Discord does
+pub fn tokenize(content: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i < content.len() {
+        let rest = &content[i..];
+        let special = if let Some(escaped) = rest.strip_prefix('\\') {
+            // Escaped characters never start a token, skip the backslash and what follows
+            i += 1 + escaped.chars().next().map_or(0, char::len_utf8);
+            continue;
+        } else if let Some(body) = rest.strip_prefix(FENCE) {
+            body.find(FENCE)
+                .map(|end| Token::Code(&rest[..end + FENCE.len() * 2]))
+        } else if rest.starts_with('`') {
+            inline_code(rest).map(Token::Code)
+        } else if rest.starts_with(SPOILER) {
+            Some(Token::Spoiler)
+        } else if rest.starts_with("<http://") || rest.starts_with("<https://") {
+            suppressed_link(rest).map(Token::SuppressedLink)
+        } else {
+            None
+        };
+
+        match special {
+            Some(token) => {
+                if text_start < i {
+                    tokens.push(Token::Text(&content[text_start..i]));
+                }
+                i += token.as_str().len();
+                text_start = i;
+                tokens.push(token);
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    if text_start < content.len() {
+        tokens.push(Token::Text(&content[text_start..]));
+    }
+
+    tokens
+}
+
+/// Matches a code span closed by a backtick run of the same length
+fn inline_code(rest: &str) -> Option<&str> {
+    let ticks = rest.len() - rest.trim_start_matches('`').len();
+    let body = &rest[ticks..];
+    let closing = "`".repeat(ticks);
+
+    let mut offset = 0;
+    while let Some(found) = body[offset..].find(&closing) {
+        let start = offset + found;
+        let run = body[start..].len() - body[start..].trim_start_matches('`').len();
+        if run == ticks && start > 0 {
+            return Some(&rest[..ticks + start + ticks]);
+        }
+        offset = start + run;
+    }
+
+    None
+}
+
+/// Matches `<url>` where the url has no whitespace in it
+fn suppressed_link(rest: &str) -> Option<&str> {
+    let end = rest.find('>')?;
+    if rest[..end].contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some(&rest[..=end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn roundtrip(content: &str) -> String {
+        tokenize(content).iter().map(|t| t.as_str()).collect()
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(
+            tokenize("just https://x.com/a/status/1 here"),
+            vec![Token::Text("just https://x.com/a/status/1 here")]
+        );
+        assert_eq!(tokenize(""), vec![]);
+    }
+
+    #[test]
+    fn test_code_block() {
+        assert_eq!(
+            tokenize("a ```rs\nhttps://x.com/a/status/1\n``` b"),
+            vec![
+                Token::Text("a "),
+                Token::Code("```rs\nhttps://x.com/a/status/1\n```"),
+                Token::Text(" b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_code() {
+        assert_eq!(
+            tokenize("`https://x.com` and ``a ` b``"),
+            vec![
+                Token::Code("`https://x.com`"),
+                Token::Text(" and "),
+                Token::Code("``a ` b``"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_code() {
+        assert_eq!(
+            tokenize("```https://x.com"),
+            vec![Token::Text("```https://x.com")]
+        );
+        assert_eq!(
+            tokenize("`https://x.com"),
+            vec![Token::Text("`https://x.com")]
+        );
+    }
+
+    #[test]
+    fn test_suppressed_link() {
+        assert_eq!(
+            tokenize("see <https://x.com/a/status/1>!"),
+            vec![
+                Token::Text("see "),
+                Token::SuppressedLink("<https://x.com/a/status/1>"),
+                Token::Text("!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_angle_brackets_that_are_not_links() {
+        assert_eq!(tokenize("a < b > c"), vec![Token::Text("a < b > c")]);
+        assert_eq!(
+            tokenize("<https://x.com oops>"),
+            vec![Token::Text("<https://x.com oops>")]
+        );
+        assert_eq!(
+            tokenize("<https://x.com"),
+            vec![Token::Text("<https://x.com")]
+        );
+    }
+
+    #[test]
+    fn test_spoilers() {
+        assert_eq!(
+            tokenize("||https://x.com|| after"),
+            vec![
+                Token::Spoiler,
+                Token::Text("https://x.com"),
+                Token::Spoiler,
+                Token::Text(" after"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_tokens() {
+        assert_eq!(
+            tokenize("||`https://x.com` <https://x.com>||"),
+            vec![
+                Token::Spoiler,
+                Token::Code("`https://x.com`"),
+                Token::Text(" "),
+                Token::SuppressedLink("<https://x.com>"),
+                Token::Spoiler,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_hides_other_markup() {
+        assert_eq!(
+            tokenize("```||<https://x.com>||```"),
+            vec![Token::Code("```||<https://x.com>||```")]
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            tokenize("\\||not a spoiler\\|| \\`https://x.com\\`"),
+            vec![Token::Text("\\||not a spoiler\\|| \\`https://x.com\\`")]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for content in [
+            "plain",
+            "||a|| `b` ```c``` <https://d> \\e",
+            "unicode ✨ ||ネコ|| `🐈`",
+            "trailing backslash \\",
+            "```unterminated `nested`",
+        ] {
+            assert_eq!(roundtrip(content), content);
+        }
+    }
+}
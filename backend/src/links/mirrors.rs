@@ -0,0 +1,202 @@
+use super::RULE_CACHE;
+use crate::REQWEST_CLIENT;
+use fancy_regex::Regex;
+use futures::future;
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+use tokio::{
+    task,
+    time::{self, Duration},
+};
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref HOST_REGEX: Regex =
+        Regex::new(r"https?://([^/\s)]+)").expect("Regex should compile");
+    pub static ref MIRROR_HEALTH: MirrorHealth = MirrorHealth::new();
+}
+
+/// Last known health of every mirror host, hosts that were never probed count as healthy
+///
+/// Uses a blocking lock so rewriting can stay synchronous, it is never held across an await
+#[derive(Default)]
+pub struct MirrorHealth {
+    hosts: RwLock<HashMap<String, bool>>,
+}
+
+impl MirrorHealth {
+    pub fn new() -> Self {
+        Self {
+            hosts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_healthy(&self, host: &str) -> bool {
+        match self.hosts.read() {
+            Ok(guard) => guard.get(host).copied().unwrap_or(true),
+            Err(_) => true,
+        }
+    }
+
+    pub fn set(&self, host: String, healthy: bool) {
+        if let Ok(mut guard) = self.hosts.write() {
+            guard.insert(host, healthy);
+        }
+    }
+
+    /// Every probed host and whether it was up, sorted by host
+    pub fn snapshot(&self) -> BTreeMap<String, bool> {
+        match self.hosts.read() {
+            Ok(guard) => guard.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+
+    /// Returns the first template whose mirror is up
+    ///
+    /// Templates without a fixed host, like `$1.example.com` or plain text, are always usable
+    pub fn pick<'a>(&self, templates: impl IntoIterator<Item = &'a String>) -> Option<&'a str> {
+        templates
+            .into_iter()
+            .find(|template| mirror_host(template).is_none_or(|host| self.is_healthy(&host)))
+            .map(String::as_str)
+    }
+}
+
+/// The host a replacement template points at
+pub fn mirror_host(template: &str) -> Option<String> {
+    let host = HOST_REGEX.captures(template).ok()??.get(1)?.as_str();
+    if host.contains('$') {
+        return None;
+    }
+
+    Some(host.to_lowercase())
+}
+
+/// A mirror counts as healthy if it answers without a server error
+pub async fn probe(url: &str) -> bool {
+    match REQWEST_CLIENT.get(url).timeout(PROBE_TIMEOUT).send().await {
+        Ok(response) => !response.status().is_server_error(),
+        Err(e) => {
+            warn!("Mirror probe for {} failed, {}", url, e);
+            false
+        }
+    }
+}
+
+pub struct MirrorManager;
+impl MirrorManager {
+    /// Probes every mirror used by a rule on startup and then every few minutes
+    pub fn start() -> Self {
+        info!("Spawning new mirror manager");
+        task::spawn(async move {
+            loop {
+                probe_all().await;
+                time::sleep(PROBE_INTERVAL).await;
+            }
+        });
+
+        Self
+    }
+}
+
+async fn probe_all() {
+    let mut hosts = RULE_CACHE
+        .all_patterns()
+        .await
+        .iter()
+        .flat_map(|p| {
+            p.templates()
+                .filter_map(|t| mirror_host(t))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+
+    let results = future::join_all(
+        hosts
+            .iter()
+            .map(|host| async move { probe(&format!("https://{}/", host)).await }),
+    )
+    .await;
+    for (host, healthy) in hosts.into_iter().zip(results) {
+        if !healthy {
+            warn!("Mirror {} is down", host);
+        }
+        MIRROR_HEALTH.set(host, healthy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answers every request with `status`
+    async fn status_server(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0; 1024];
+                let _ = stream.read(&mut buffer).await.unwrap();
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_mirror_host() {
+        assert_eq!(
+            mirror_host("[Twitter](https://FXtwitter.com/$1/status/$2)").as_deref(),
+            Some("fxtwitter.com")
+        );
+        assert_eq!(
+            mirror_host("https://vm.vxtiktok.com/$1").as_deref(),
+            Some("vm.vxtiktok.com")
+        );
+        assert_eq!(mirror_host("https://$1.example.com/"), None);
+        assert_eq!(mirror_host("bar"), None);
+    }
+
+    #[test]
+    fn test_pick_first_healthy() {
+        let health = MirrorHealth::new();
+        let templates = vec![
+            "https://first.example/$1".to_string(),
+            "https://second.example/$1".to_string(),
+        ];
+        assert_eq!(health.pick(&templates), Some("https://first.example/$1"));
+
+        health.set("first.example".to_string(), false);
+        assert_eq!(health.pick(&templates), Some("https://second.example/$1"));
+
+        health.set("second.example".to_string(), false);
+        assert_eq!(health.pick(&templates), None);
+
+        health.set("first.example".to_string(), true);
+        assert_eq!(health.pick(&templates), Some("https://first.example/$1"));
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        assert!(probe(&status_server("200 OK").await).await);
+        assert!(probe(&status_server("404 Not Found").await).await);
+        assert!(!probe(&status_server("503 Service Unavailable").await).await);
+        // Nothing is listening on port 9
+        assert!(!probe("http://127.0.0.1:9/").await);
+    }
+}
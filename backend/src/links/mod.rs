@@ -0,0 +1,1372 @@
+use ::serenity::all::{
+    ChannelId, CreateAllowedMentions, CreateAttachment, CreateInteractionResponseMessage,
+    CreateMessage, EditMessage, MessageId, model::channel::MessageFlags,
+};
+use common::context::get_context_wrapper;
+use database::{
+    links::{
+        fetch_all_guild_settings, fetch_all_provider_settings, fetch_all_rules, set_fix_mode,
+        sync_builtin_rules, untrack_repost,
+    },
+    models::{LinkFixMode, LinkProviderSettings, NewLinkRule},
+    settings::{fetch_all_opted_out_users, set_links_opt_out},
+};
+use expand::SHORT_LINKS;
+use fancy_regex::Regex;
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use markdown::Token;
+use mirrors::MIRROR_HEALTH;
+use poise::serenity_prelude as serenity;
+use prefilter::{Prefilter, RuledOut};
+use repost::Carryover;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::{
+    sync::{Mutex, OnceCell, RwLock},
+    time::Duration,
+};
+use tracking::{TRACKING_FILTER, TRACKING_PROVIDER};
+
+const BUTTON_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    #[derive(Debug)]
+    static ref BUILT_PATTERNS: Vec<Arc<BuiltPattern>> = build_all().expect("All patterns should build according to tests");
+    pub static ref RULE_CACHE: RuleCache = RuleCache::new();
+    pub static ref PROVIDER_TOGGLES: ProviderToggles = ProviderToggles::new();
+    pub static ref OPT_OUT_CACHE: OptOutCache = OptOutCache::new();
+    pub static ref GUILD_SETTINGS: GuildLinkSettings = GuildLinkSettings::new();
+}
+
+pub mod expand;
+pub mod markdown;
+pub mod mirrors;
+pub mod prefilter;
+pub mod repost;
+pub mod stats;
+pub mod tracking;
+pub mod video;
+pub mod webhook;
+
+#[derive(Deserialize)]
+struct LoadedJson {
+    provider: String,
+    pattern: String,
+    replacement: String,
+    #[serde(default)]
+    fallbacks: Vec<String>,
+    #[serde(default)]
+    examples: Vec<RuleExample>,
+    /// Links the pattern must not match
+    #[serde(default)]
+    counterexamples: Vec<String>,
+}
+
+/// A link a rule has to rewrite and what it has to become
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleExample {
+    pub input: String,
+    pub output: String,
+}
+
+#[derive(Error, Debug)]
+pub enum RuleError {
+    #[error("That pattern doesn't compile: {0}")]
+    InvalidPattern(#[from] Box<fancy_regex::Error>),
+
+    #[error("`{input}` was rewritten to `{actual}` instead of `{expected}`")]
+    WrongOutput {
+        input: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("`{0}` should not match the pattern")]
+    UnexpectedMatch(String),
+
+    #[error("The fallback `{template}` uses `${group}`, which `{input}` doesn't capture")]
+    MissingGroup {
+        template: String,
+        group: String,
+        input: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct BuiltPattern {
+    provider: String,
+    pattern: Regex,
+    replacement: String,
+    fallbacks: Vec<String>,
+}
+
+impl BuiltPattern {
+    pub fn new(
+        provider: String,
+        pattern: Regex,
+        replacement: String,
+        fallbacks: Vec<String>,
+    ) -> Self {
+        Self {
+            provider,
+            pattern,
+            replacement,
+            fallbacks,
+        }
+    }
+
+    /// The replacement followed by its fallbacks, in the order they should be tried
+    fn templates(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.replacement).chain(self.fallbacks.iter())
+    }
+
+    /// The first replacement whose mirror is up, `None` leaves links for this rule alone
+    fn healthy_replacement(&self) -> Option<&str> {
+        MIRROR_HEALTH.pick(self.templates())
+    }
+}
+
+/// Compiled rewrite rules, kept in memory so `fix_links` never has to touch the database
+///
+/// Starts out with the embedded `patterns.json` defaults and is replaced with the
+/// stored rules on every [`RuleCache::reload`]
+pub struct RuleCache {
+    rules: RwLock<CachedRules>,
+}
+
+#[derive(Default)]
+struct CachedRules {
+    global: Vec<Arc<BuiltPattern>>,
+    guilds: HashMap<i64, Vec<Arc<BuiltPattern>>>,
+    /// Covers every rule in every guild so it only has to be built on reload
+    prefilter: Arc<Prefilter>,
+}
+
+impl RuleCache {
+    pub fn new() -> Self {
+        Self {
+            rules: RwLock::new(CachedRules {
+                global: BUILT_PATTERNS.clone(),
+                guilds: HashMap::new(),
+                prefilter: Arc::new(Prefilter::new(&BUILT_PATTERNS)),
+            }),
+        }
+    }
+
+    /// Returns the global rules followed by any rules scoped to `guild_id`
+    pub async fn patterns_for(&self, guild_id: Option<i64>) -> Vec<Arc<BuiltPattern>> {
+        let guard = self.rules.read().await;
+        let mut patterns = guard.global.clone();
+        if let Some(guild) = guild_id.and_then(|id| guard.guilds.get(&id)) {
+            patterns.extend(guild.iter().cloned());
+        }
+        patterns
+    }
+
+    /// Every rule in every guild, used to find which mirrors need probing
+    pub async fn all_patterns(&self) -> Vec<Arc<BuiltPattern>> {
+        let guard = self.rules.read().await;
+        let mut patterns = guard.global.clone();
+        patterns.extend(guard.guilds.values().flatten().cloned());
+        patterns
+    }
+
+    pub async fn prefilter(&self) -> Arc<Prefilter> {
+        self.rules.read().await.prefilter.clone()
+    }
+
+    /// Every provider identifier that has a rule in `guild_id`
+    pub async fn providers_for(&self, guild_id: Option<i64>) -> BTreeSet<String> {
+        let mut providers = self
+            .patterns_for(guild_id)
+            .await
+            .iter()
+            .map(|p| p.provider.clone())
+            .collect::<BTreeSet<_>>();
+        providers.insert(TRACKING_PROVIDER.to_string());
+        providers
+    }
+
+    /// Rebuilds the cache from the database, rules that fail to compile are skipped
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let mut rules = CachedRules::default();
+
+        for stored in fetch_all_rules().await? {
+            let built = match build_regex(&stored.pattern) {
+                Ok(pattern) => Arc::new(BuiltPattern::new(
+                    stored.provider,
+                    pattern,
+                    stored.replacement,
+                    stored.fallbacks,
+                )),
+                Err(e) => {
+                    warn!("Skipping link rule {}, {}", stored.id, e);
+                    continue;
+                }
+            };
+
+            match stored.guild_id {
+                Some(guild) => rules.guilds.entry(guild).or_default().push(built),
+                None => rules.global.push(built),
+            }
+        }
+
+        let mut all = rules.global.clone();
+        all.extend(rules.guilds.values().flatten().cloned());
+        rules.prefilter = Arc::new(Prefilter::new(&all));
+
+        let mut guard = self.rules.write().await;
+        *guard = rules;
+        info!("Reloaded link rule cache");
+
+        Ok(())
+    }
+}
+
+impl Default for RuleCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per guild and per channel provider overrides, everything is enabled unless a row says otherwise
+pub struct ProviderToggles {
+    settings: RwLock<HashMap<i64, Vec<LinkProviderSettings>>>,
+}
+
+impl ProviderToggles {
+    pub fn new() -> Self {
+        Self {
+            settings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `provider` should be fixed in a channel
+    pub async fn is_enabled(&self, guild_id: Option<i64>, channel_id: i64, provider: &str) -> bool {
+        let Some(guild_id) = guild_id else {
+            return true;
+        };
+        let guard = self.settings.read().await;
+        guard
+            .get(&guild_id)
+            .is_none_or(|settings| resolve_toggle(settings, channel_id, Some(provider)))
+    }
+
+    /// Whether an opt in `provider`, like [`TRACKING_PROVIDER`], was turned on for a channel
+    pub async fn is_opted_in(
+        &self,
+        guild_id: Option<i64>,
+        channel_id: i64,
+        provider: &str,
+    ) -> bool {
+        let Some(guild_id) = guild_id else {
+            return false;
+        };
+        let guard = self.settings.read().await;
+        guard
+            .get(&guild_id)
+            .is_some_and(|settings| resolve_opt_in(settings, channel_id, provider))
+    }
+
+    /// Whether any provider could be fixed in a channel, used to skip the link pipeline entirely
+    pub async fn any_enabled(&self, guild_id: Option<i64>, channel_id: i64) -> bool {
+        let Some(guild_id) = guild_id else {
+            return true;
+        };
+        let guard = self.settings.read().await;
+        guard.get(&guild_id).is_none_or(|settings| {
+            resolve_toggle(settings, channel_id, None)
+                || settings
+                    .iter()
+                    .filter_map(|s| s.provider.as_deref())
+                    .any(|provider| resolve_toggle(settings, channel_id, Some(provider)))
+        })
+    }
+
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let mut settings: HashMap<i64, Vec<LinkProviderSettings>> = HashMap::new();
+        for row in fetch_all_provider_settings().await? {
+            settings.entry(row.guild_id).or_default().push(row);
+        }
+
+        let mut guard = self.settings.write().await;
+        *guard = settings;
+        info!("Reloaded link provider settings");
+
+        Ok(())
+    }
+}
+
+impl Default for ProviderToggles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How each guild wants fixed links to be posted
+#[derive(Default)]
+pub struct GuildLinkSettings {
+    modes: RwLock<HashMap<i64, LinkFixMode>>,
+}
+
+impl GuildLinkSettings {
+    pub fn new() -> Self {
+        Self {
+            modes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn mode(&self, guild_id: Option<i64>) -> LinkFixMode {
+        let Some(guild_id) = guild_id else {
+            return LinkFixMode::default();
+        };
+        let guard = self.modes.read().await;
+        guard.get(&guild_id).copied().unwrap_or_default()
+    }
+
+    pub async fn set_mode(&self, guild_id: i64, mode: LinkFixMode) -> anyhow::Result<()> {
+        set_fix_mode(guild_id, mode).await?;
+        self.modes.write().await.insert(guild_id, mode);
+        info!("Set link fix mode for {} to {}", guild_id, mode);
+
+        Ok(())
+    }
+
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let modes = fetch_all_guild_settings()
+            .await?
+            .into_iter()
+            .map(|s| (s.guild_id, s.mode))
+            .collect();
+
+        let mut guard = self.modes.write().await;
+        *guard = modes;
+        info!("Reloaded guild link settings");
+
+        Ok(())
+    }
+}
+
+/// Users that never want their messages touched by the link fixer
+#[derive(Default)]
+pub struct OptOutCache {
+    initialized: OnceCell<()>,
+    users: Mutex<HashSet<i64>>,
+}
+
+impl OptOutCache {
+    pub fn new() -> Self {
+        Self {
+            initialized: OnceCell::new(),
+            users: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Users count as opted out until the stored preferences could be loaded
+    pub async fn check(&self, id: i64) -> bool {
+        if !self.ensure_initialized().await {
+            return true;
+        }
+        let guard = self.users.lock().await;
+        guard.contains(&id)
+    }
+
+    /// Stores the preference and updates the cache once the write succeeds
+    pub async fn set(&self, id: i64, opt_out: bool) -> anyhow::Result<()> {
+        set_links_opt_out(id, opt_out).await?;
+        // Loading afterwards already picks up the write
+        if !self.ensure_initialized().await {
+            return Ok(());
+        }
+
+        let mut guard = self.users.lock().await;
+        if opt_out {
+            guard.insert(id);
+        } else {
+            guard.remove(&id);
+        }
+        info!("Set link opt out for {} to {}", id, opt_out);
+
+        Ok(())
+    }
+
+    /// Loads the stored preferences, a failed load is retried on the next call
+    async fn ensure_initialized(&self) -> bool {
+        let loaded = self
+            .initialized
+            .get_or_try_init(|| async {
+                let ids = fetch_all_opted_out_users().await?;
+                let mut guard = self.users.lock().await;
+                *guard = ids.into_iter().collect();
+                info!("Link opt out cache initialized");
+                Ok::<_, anyhow::Error>(())
+            })
+            .await;
+        if let Err(e) = &loaded {
+            error!("Failed to populate link opt out cache, {}", e);
+        }
+
+        loaded.is_ok()
+    }
+}
+
+/// Picks the most specific setting for a provider in a channel
+///
+/// Channel settings win over guild settings and a named provider wins over "all providers",
+/// channel + provider, channel + all, guild + provider, guild + all, then enabled by default
+fn resolve_toggle(
+    settings: &[LinkProviderSettings],
+    channel_id: i64,
+    provider: Option<&str>,
+) -> bool {
+    let find = |channel: Option<i64>, provider: Option<&str>| {
+        settings
+            .iter()
+            .find(|s| s.channel_id == channel && s.provider.as_deref() == provider)
+            .map(|s| s.enabled)
+    };
+
+    provider
+        .and_then(|p| find(Some(channel_id), Some(p)))
+        .or_else(|| find(Some(channel_id), None))
+        .or_else(|| provider.and_then(|p| find(None, Some(p))))
+        .or_else(|| find(None, None))
+        .unwrap_or(true)
+}
+
+/// Like [`resolve_toggle`], but only a setting naming `provider` turns it on and it stays
+/// off by default, channel + provider, channel + all when it is off, guild + provider,
+/// then disabled
+fn resolve_opt_in(settings: &[LinkProviderSettings], channel_id: i64, provider: &str) -> bool {
+    let find = |channel: Option<i64>, provider: Option<&str>| {
+        settings
+            .iter()
+            .find(|s| s.channel_id == channel && s.provider.as_deref() == provider)
+            .map(|s| s.enabled)
+    };
+
+    find(Some(channel_id), Some(provider))
+        .or_else(|| find(Some(channel_id), None).filter(|enabled| !enabled))
+        .or_else(|| find(None, Some(provider)))
+        .unwrap_or(false)
+}
+
+fn load_json_patterns() -> Result<Vec<LoadedJson>, Box<dyn std::error::Error>> {
+    let file = include_str!("../../../patterns.json");
+    let deserialized: Vec<LoadedJson> = serde_json::from_str(file)?;
+
+    Ok(deserialized)
+}
+
+fn build_regex(pattern: &str) -> Result<Regex, Box<fancy_regex::Error>> {
+    match Regex::new(pattern) {
+        Ok(regex) => Ok(regex),
+        Err(e) => {
+            error!("Failed to compile regex pattern '{}': {}", pattern, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+fn build_all() -> Result<Vec<Arc<BuiltPattern>>, Box<dyn std::error::Error>> {
+    let mut patterns: Vec<Arc<BuiltPattern>> = Vec::new();
+
+    match load_json_patterns() {
+        Ok(jsons) => {
+            for item in jsons.iter() {
+                let regex_pattern = build_regex(&item.pattern)?;
+                patterns.push(Arc::new(BuiltPattern::new(
+                    item.provider.to_owned(),
+                    regex_pattern,
+                    item.replacement.to_owned(),
+                    item.fallbacks.to_owned(),
+                )));
+            }
+        }
+        Err(e) => {
+            error!("Failed to load json file, error: {}", e);
+            return Err(e);
+        }
+    };
+
+    info!("Built {} patterns successfully", patterns.len());
+
+    Ok(patterns)
+}
+
+/// The rules embedded from `patterns.json`
+pub fn builtin_patterns() -> Vec<Arc<BuiltPattern>> {
+    BUILT_PATTERNS.clone()
+}
+
+/// Writes the embedded `patterns.json` into the database as the global builtin rules
+///
+/// Nothing is written unless every rule passes its examples
+pub async fn seed_default_rules() -> anyhow::Result<()> {
+    let defaults = load_json_patterns()
+        .map_err(|e| anyhow::anyhow!("Failed to load json patterns, {}", e))?
+        .into_iter()
+        .map(|item| {
+            validate_rule(
+                &item.pattern,
+                &item.replacement,
+                &item.fallbacks,
+                &item.examples,
+                &item.counterexamples,
+            )?;
+            Ok(NewLinkRule {
+                guild_id: None,
+                pattern: item.pattern,
+                replacement: item.replacement,
+                fallbacks: item.fallbacks,
+                builtin: true,
+                provider: item.provider,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    sync_builtin_rules(defaults).await
+}
+
+/// Checks that a rule compiles, rewrites every example into its expected output
+/// and leaves every counterexample alone
+///
+/// Fallbacks have no expected output of their own, they only have to fill in capture groups
+/// the pattern has for every example
+pub fn validate_rule(
+    pattern: &str,
+    replacement: &str,
+    fallbacks: &[String],
+    examples: &[RuleExample],
+    counterexamples: &[String],
+) -> Result<(), RuleError> {
+    let regex = build_regex(pattern)?;
+    for example in examples {
+        let actual = regex.replace_all(&example.input, replacement);
+        if actual != example.output {
+            return Err(RuleError::WrongOutput {
+                input: example.input.clone(),
+                expected: example.output.clone(),
+                actual: actual.into_owned(),
+            });
+        }
+
+        let Some(captures) = regex.captures(&example.input).map_err(Box::new)? else {
+            continue;
+        };
+        for template in fallbacks {
+            for group in template_groups(template) {
+                let captured = match group.parse::<usize>() {
+                    Ok(index) => captures.get(index),
+                    Err(_) => captures.name(group),
+                };
+                if captured.is_none() {
+                    return Err(RuleError::MissingGroup {
+                        template: template.clone(),
+                        group: group.to_string(),
+                        input: example.input.clone(),
+                    });
+                }
+            }
+        }
+    }
+    for counterexample in counterexamples {
+        if regex.is_match(counterexample).map_err(Box::new)? {
+            return Err(RuleError::UnexpectedMatch(counterexample.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The capture groups `template` refers to, like `1` for `$1` and `name` for `${name}`
+fn template_groups(template: &str) -> Vec<&str> {
+    let mut groups = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        rest = &rest[start + 1..];
+        if let Some(escaped) = rest.strip_prefix('$') {
+            rest = escaped;
+        } else if let Some((group, after)) = rest
+            .strip_prefix('{')
+            .and_then(|braced| braced.split_once('}'))
+        {
+            groups.push(group);
+            rest = after;
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if end > 0 {
+                groups.push(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+    }
+    groups
+}
+
+/// A message after every applicable rule has run over it
+#[derive(Debug, Default, PartialEq)]
+pub struct FixedMessage {
+    /// The full message content with links replaced
+    pub content: String,
+    /// Only the replacements, in the order they were made
+    pub links: Vec<String>,
+    /// The provider behind each entry in `links`
+    pub providers: Vec<String>,
+    /// The link each entry in `links` was made from, after tracking parameters were removed
+    pub sources: Vec<String>,
+}
+
+impl FixedMessage {
+    fn push(&mut self, link: String, provider: &str, source: &str) {
+        self.links.push(link);
+        self.providers.push(provider.to_owned());
+        self.sources.push(source.to_owned());
+    }
+}
+
+/// Strips tracking parameters from every url in `text`
+///
+/// A url is only cleaned when a rule is going to rewrite it anyway or when `clean_alone` is set,
+/// urls cleaned on their own are added to `links` under [`TRACKING_PROVIDER`]
+fn strip_tracking(
+    text: &str,
+    patterns: &[Arc<BuiltPattern>],
+    ruled_out: &RuledOut,
+    clean_alone: bool,
+    fixed: &mut FixedMessage,
+) -> Result<String, Box<fancy_regex::Error>> {
+    let mut cleaned = String::with_capacity(text.len());
+    let mut last = 0;
+    for range in tracking::find_urls(text) {
+        let Some(clean) = TRACKING_FILTER.clean(&text[range.clone()]) else {
+            continue;
+        };
+
+        let mut rewritten_by_rule = false;
+        for built in patterns.iter() {
+            if !ruled_out.contains(built) && built.pattern.is_match(&clean)? {
+                rewritten_by_rule = true;
+                break;
+            }
+        }
+        if !rewritten_by_rule && !clean_alone {
+            continue;
+        }
+
+        cleaned.push_str(&text[last..range.start]);
+        cleaned.push_str(&clean);
+        last = range.end;
+        if !rewritten_by_rule {
+            fixed.push(clean.clone(), TRACKING_PROVIDER, &clean);
+        }
+    }
+    cleaned.push_str(&text[last..]);
+
+    Ok(cleaned)
+}
+
+fn rewrite(
+    content: &str,
+    patterns: &[Arc<BuiltPattern>],
+    prefilter: &Prefilter,
+    clean_alone: bool,
+) -> Result<Option<FixedMessage>, Box<fancy_regex::Error>> {
+    let mut fixed = FixedMessage::default();
+    let mut result = String::with_capacity(content.len());
+    for token in markdown::tokenize(content) {
+        // Code, suppressed links and spoiler markers are copied over untouched
+        let Token::Text(text) = token else {
+            result.push_str(token.as_str());
+            continue;
+        };
+
+        // Tracking parameters never touch the scheme and host the prefilter looks for
+        let ruled_out = prefilter.ruled_out(text);
+        let mut rewritten = strip_tracking(text, patterns, &ruled_out, clean_alone, &mut fixed)?;
+        let cleaned = rewritten.clone();
+        // Check if a message contains a link within the loaded patterns
+        for built in patterns.iter() {
+            if ruled_out.contains(built) || !built.pattern.is_match(&cleaned)? {
+                continue;
+            }
+            if let Some(replacement) = built.healthy_replacement() {
+                for captures in built.pattern.captures_iter(&rewritten) {
+                    let captures = captures?;
+                    let mut link = String::new();
+                    captures.expand(replacement, &mut link);
+                    let source = captures.get(0).map_or("", |m| m.as_str());
+                    fixed.push(link, &built.provider, source);
+                }
+                rewritten = built
+                    .pattern
+                    .replace_all(&rewritten, replacement)
+                    .to_string();
+                debug!("{}", rewritten)
+            }
+        }
+        result.push_str(&rewritten);
+    }
+
+    if result == content {
+        Ok(None)
+    } else {
+        fixed.content = result;
+        Ok(Some(fixed))
+    }
+}
+
+/// Runs `content` through `patterns` without expanding short links, returns the fixed content
+pub fn apply_patterns(
+    content: &str,
+    patterns: &[Arc<BuiltPattern>],
+    prefilter: &Prefilter,
+    clean_alone: bool,
+) -> Result<Option<String>, Box<fancy_regex::Error>> {
+    Ok(rewrite(content, patterns, prefilter, clean_alone)?.map(|fixed| fixed.content))
+}
+
+/// Every rule that applies in `guild_id`, skipping providers that are turned off in `channel_id`
+/// and rules where every mirror is down
+async fn enabled_patterns(guild_id: Option<i64>, channel_id: i64) -> Vec<Arc<BuiltPattern>> {
+    let mut patterns = Vec::new();
+    for pattern in RULE_CACHE.patterns_for(guild_id).await {
+        if pattern.healthy_replacement().is_some()
+            && PROVIDER_TOGGLES
+                .is_enabled(guild_id, channel_id, &pattern.provider)
+                .await
+        {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
+
+/// Runs `content` through every rule that applies in `guild_id`, skipping providers
+/// that are turned off in `channel_id`
+pub async fn fix_content(
+    guild_id: Option<i64>,
+    channel_id: i64,
+    content: &str,
+) -> Result<Option<String>, Box<fancy_regex::Error>> {
+    let clean_alone = PROVIDER_TOGGLES
+        .is_opted_in(guild_id, channel_id, TRACKING_PROVIDER)
+        .await;
+    let patterns = enabled_patterns(guild_id, channel_id).await;
+    let content = SHORT_LINKS.expand_links(content, &patterns).await?;
+    let prefilter = RULE_CACHE.prefilter().await;
+    apply_patterns(&content, &patterns, &prefilter, clean_alone)
+}
+
+pub async fn fix_message(
+    message: &serenity::Message,
+) -> Result<Option<FixedMessage>, Box<fancy_regex::Error>> {
+    let guild_id = message.guild_id.map(|id| id.get() as i64);
+    let channel_id = message.channel_id.get() as i64;
+    let clean_alone = PROVIDER_TOGGLES
+        .is_opted_in(guild_id, channel_id, TRACKING_PROVIDER)
+        .await;
+    let patterns = enabled_patterns(guild_id, channel_id).await;
+    let content = SHORT_LINKS
+        .expand_links(&message.content, &patterns)
+        .await?;
+    let prefilter = RULE_CACHE.prefilter().await;
+    rewrite(&content, &patterns, &prefilter, clean_alone)
+}
+
+pub async fn fix_links(
+    message: &poise::serenity_prelude::Message,
+) -> Result<Option<String>, Box<fancy_regex::Error>> {
+    Ok(fix_message(message).await?.map(|fixed| fixed.content))
+}
+
+/// What the bot posts in place of a message in [`LinkFixMode::Reply`]
+pub fn reply_content(message_owner: u64, message_content: &str) -> String {
+    format!("<@{}>: {}", message_owner, message_content)
+}
+
+/// What the bot posts next to a message in [`LinkFixMode::Suppress`]
+pub fn companion_content(links: &[String]) -> String {
+    links.join("\n")
+}
+
+pub async fn message_handler(
+    message_content: String,
+    original: &serenity::Message,
+    reply_target: &serenity::Message,
+    carryover: &Carryover,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let message_owner = original.author.id.get();
+    let builder = CreateMessage::new()
+        .content(reply_content(message_owner, &message_content))
+        .flags(MessageFlags::SUPPRESS_NOTIFICATIONS)
+        .reference_message(reply_target)
+        .add_files(carryover.attachments.clone())
+        .sticker_ids(carryover.stickers.clone());
+
+    let sent = send_with_delete_button(builder, message_owner, original.channel_id).await?;
+    repost::track(sent, original, LinkFixMode::Reply, !carryover.complete).await;
+
+    Ok(())
+}
+
+/// Suppresses the embeds on `original` and replies to it with just the fixed links,
+/// leaving the message itself untouched
+pub async fn companion_handler(
+    links: Vec<String>,
+    attachments: Vec<CreateAttachment>,
+    original: &serenity::Message,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ctx = get_context_wrapper();
+    // Needs Manage Messages as well, without it the companion reply is still useful
+    if let Err(e) = original
+        .channel_id
+        .edit_message(ctx, original.id, EditMessage::new().suppress_embeds(true))
+        .await
+    {
+        warn!("Failed to suppress embeds on {}, {}", original.id, e);
+    }
+
+    let builder = CreateMessage::new()
+        .content(companion_content(&links))
+        .add_files(attachments)
+        .flags(MessageFlags::SUPPRESS_NOTIFICATIONS)
+        .reference_message(original)
+        .allowed_mentions(CreateAllowedMentions::new());
+
+    let sent =
+        send_with_delete_button(builder, original.author.id.get(), original.channel_id).await?;
+    repost::track(sent, original, LinkFixMode::Suppress, true).await;
+
+    Ok(())
+}
+
+/// Sends `builder` with a button that lets `message_owner` delete it for a while
+async fn send_with_delete_button(
+    builder: CreateMessage,
+    message_owner: u64,
+    channel_target: ChannelId,
+) -> Result<MessageId, Box<dyn std::error::Error>> {
+    let ctx = get_context_wrapper();
+    let components = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(format!("{}", message_owner))
+            .emoji(serenity::ReactionType::Unicode("\u{1F5D1}".to_string()))
+            .style(serenity::ButtonStyle::Danger),
+    ]);
+
+    let mut message = channel_target
+        .send_message(ctx, builder.components(vec![components]))
+        .await?;
+    let sent = message.id;
+
+    tokio::spawn(async move {
+        let mut interaction_stream = message
+            .await_component_interactions(&ctx.shard)
+            .timeout(BUTTON_TIMEOUT)
+            .stream();
+
+        // Becomes none at the end of the timeout and continues
+        while let Some(interaction) = interaction_stream.next().await {
+            // `custom_id` will ALWAYS be parsable
+            #[allow(clippy::unwrap_used)]
+            if interaction.user.id.get() == interaction.data.custom_id.parse::<u64>().unwrap() {
+                match interaction.message.delete(&ctx).await {
+                    Ok(_) => {
+                        if let Err(e) = untrack_repost(sent.get() as i64).await {
+                            warn!("Failed to untrack deleted repost {}, {}", sent, e);
+                        }
+                    }
+                    Err(why) => error!("Failed to delete message from interaction, {}", why),
+                };
+            } else {
+                warn!("{} cannot press this button", interaction.user.name);
+                match interaction
+                    .create_response(
+                        &ctx,
+                        serenity::CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::default()
+                                .content("You are not the owner of this message!")
+                                .ephemeral(true),
+                        ),
+                    )
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(e) => error!("Interaction failure, {}", e),
+                }
+            };
+
+            match interaction
+                .create_response(&ctx, serenity::CreateInteractionResponse::Acknowledge)
+                .await
+            {
+                Ok(_) => (),
+                Err(e) => error!("Interaction failure, {}", e),
+            }
+        }
+
+        // FIXME define a new error please and thankyou
+        if let Err(why) = message
+            .edit(&ctx, EditMessage::new().components(vec![]))
+            .await
+        {
+            error!("Failed to remove components, {}", why)
+        };
+    });
+
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poise::serenity_prelude::Message;
+
+    trait Content {
+        fn set_content(&mut self, content: impl Into<String>) -> &mut Self;
+    }
+
+    impl Content for Message {
+        fn set_content(&mut self, content: impl Into<String>) -> &mut Self {
+            self.content = content.into();
+            self
+        }
+    }
+
+    fn setup_test_message(content: impl Into<String>) -> Message {
+        let mut message = Message::default();
+        message.set_content(content);
+        message
+    }
+
+    #[test]
+    fn test_load_json() {
+        let json = load_json_patterns();
+        assert!(json.is_ok())
+    }
+
+    #[test]
+    fn test_build_patterns() {
+        let json = load_json_patterns().unwrap();
+        let built = build_all();
+        assert!(built.is_ok());
+        assert_eq!(built.unwrap().len(), json.len())
+    }
+
+    #[test]
+    fn test_pattern_examples() {
+        for item in load_json_patterns().unwrap() {
+            assert!(
+                !item.examples.is_empty() && !item.counterexamples.is_empty(),
+                "`{}` needs at least one example and counterexample",
+                item.pattern
+            );
+            if let Err(e) = validate_rule(
+                &item.pattern,
+                &item.replacement,
+                &item.fallbacks,
+                &item.examples,
+                &item.counterexamples,
+            ) {
+                panic!("`{}` failed its examples, {}", item.pattern, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_rule() {
+        let examples = vec![RuleExample {
+            input: "https://example.com/a/1".to_string(),
+            output: "https://mirror.example/1".to_string(),
+        }];
+        let counterexamples = vec!["https://example.com/b/1".to_string()];
+        assert!(
+            validate_rule(
+                r"https://example\.com/a/(\d+)",
+                "https://mirror.example/$1",
+                &["[Mirror](https://other.example/${1})".to_string()],
+                &examples,
+                &counterexamples
+            )
+            .is_ok()
+        );
+        assert!(matches!(
+            validate_rule(r"https://example\.com/a/(\d+", "", &[], &[], &[]),
+            Err(RuleError::InvalidPattern(_))
+        ));
+        assert!(matches!(
+            validate_rule(
+                r"https://example\.com/a/(\d+)",
+                "https://mirror.example/$2",
+                &[],
+                &examples,
+                &[]
+            ),
+            Err(RuleError::WrongOutput { .. })
+        ));
+        assert!(matches!(
+            validate_rule(
+                r"https://example\.com/a/(\d+)",
+                "https://mirror.example/$1",
+                &["https://other.example/$2".to_string()],
+                &examples,
+                &[]
+            ),
+            Err(RuleError::MissingGroup { .. })
+        ));
+        assert!(matches!(
+            validate_rule(
+                r"https://example\.com/\w/(\d+)",
+                "https://mirror.example/$1",
+                &[],
+                &examples,
+                &counterexamples
+            ),
+            Err(RuleError::UnexpectedMatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_template_groups() {
+        assert_eq!(
+            template_groups("[Twitter Mirror](https://a.example/$1/status/${2})"),
+            ["1", "2"]
+        );
+        assert_eq!(template_groups("$name/$$1/${x}y"), ["name", "x"]);
+        assert!(template_groups("https://a.example/").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_guild_scoped_rules() {
+        let cache = RuleCache::new();
+        cache.rules.write().await.guilds.insert(
+            1,
+            vec![Arc::new(BuiltPattern::new(
+                "custom".to_string(),
+                Regex::new("foo").unwrap(),
+                "bar".to_string(),
+                vec![],
+            ))],
+        );
+
+        let scoped = cache.patterns_for(Some(1)).await;
+        assert_eq!(scoped.len(), BUILT_PATTERNS.len() + 1);
+        assert_eq!(
+            apply_patterns("foo", &scoped, &*cache.prefilter().await, false)
+                .unwrap()
+                .unwrap(),
+            "bar"
+        );
+        assert_eq!(
+            cache.patterns_for(Some(2)).await.len(),
+            BUILT_PATTERNS.len()
+        );
+        assert_eq!(cache.patterns_for(None).await.len(), BUILT_PATTERNS.len());
+    }
+
+    fn toggle(
+        channel_id: Option<i64>,
+        provider: Option<&str>,
+        enabled: bool,
+    ) -> LinkProviderSettings {
+        LinkProviderSettings {
+            id: 0,
+            guild_id: 1,
+            channel_id,
+            provider: provider.map(|p| p.to_string()),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn test_toggle_defaults_to_enabled() {
+        assert!(resolve_toggle(&[], 10, Some("tiktok")));
+        assert!(resolve_toggle(&[], 10, None));
+    }
+
+    #[test]
+    fn test_toggle_channel_provider() {
+        let settings = [toggle(Some(10), Some("tiktok"), false)];
+        assert!(!resolve_toggle(&settings, 10, Some("tiktok")));
+        assert!(resolve_toggle(&settings, 10, Some("pixiv")));
+        assert!(resolve_toggle(&settings, 11, Some("tiktok")));
+    }
+
+    #[test]
+    fn test_toggle_precedence() {
+        let settings = [
+            toggle(None, None, false),
+            toggle(None, Some("twitter"), true),
+            toggle(Some(10), None, true),
+            toggle(Some(10), Some("twitter"), false),
+        ];
+        // Guild wide everything off, but twitter on
+        assert!(!resolve_toggle(&settings, 11, Some("pixiv")));
+        assert!(resolve_toggle(&settings, 11, Some("twitter")));
+        // Channel wide everything on, but twitter off
+        assert!(resolve_toggle(&settings, 10, Some("pixiv")));
+        assert!(!resolve_toggle(&settings, 10, Some("twitter")));
+    }
+
+    #[test]
+    fn test_opt_in() {
+        assert!(!resolve_opt_in(&[], 10, TRACKING_PROVIDER));
+        // Turning everything on doesn't opt in
+        let settings = [toggle(None, None, true), toggle(Some(10), None, true)];
+        assert!(!resolve_opt_in(&settings, 10, TRACKING_PROVIDER));
+
+        let settings = [
+            toggle(None, Some(TRACKING_PROVIDER), true),
+            toggle(Some(10), Some(TRACKING_PROVIDER), false),
+        ];
+        assert!(resolve_opt_in(&settings, 11, TRACKING_PROVIDER));
+        assert!(!resolve_opt_in(&settings, 10, TRACKING_PROVIDER));
+    }
+
+    #[tokio::test]
+    async fn test_any_enabled() {
+        let toggles = ProviderToggles::new();
+        toggles.settings.write().await.insert(
+            1,
+            vec![
+                toggle(Some(10), None, false),
+                toggle(Some(11), None, false),
+                toggle(Some(11), Some("pixiv"), true),
+            ],
+        );
+
+        assert!(!toggles.any_enabled(Some(1), 10).await);
+        assert!(toggles.any_enabled(Some(1), 11).await);
+        assert!(toggles.any_enabled(Some(1), 12).await);
+        assert!(toggles.any_enabled(None, 10).await);
+    }
+
+    #[tokio::test]
+    async fn test_channel_off_beats_guild_opt_in() {
+        let toggles = ProviderToggles::new();
+        toggles.settings.write().await.insert(
+            1,
+            vec![
+                toggle(None, Some(TRACKING_PROVIDER), true),
+                toggle(None, Some("pixiv"), true),
+                toggle(Some(10), None, false),
+            ],
+        );
+
+        assert!(!toggles.is_opted_in(Some(1), 10, TRACKING_PROVIDER).await);
+        assert!(!toggles.any_enabled(Some(1), 10).await);
+        assert!(toggles.is_opted_in(Some(1), 11, TRACKING_PROVIDER).await);
+        assert!(toggles.any_enabled(Some(1), 11).await);
+    }
+
+    #[tokio::test]
+    async fn test_fix_singular_link() {
+        let test_message = setup_test_message(
+            "this is a test message, https://x.com/testaccount/status/1814183041708990884",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "this is a test message, [Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884)"
+        )
+    }
+
+    #[tokio::test]
+    async fn test_multiple_links() {
+        let test_message = setup_test_message(
+            "https://x.com/testaccount/status/1814183041708990884, https://twitter.com/testaccount/status/1814183041708990884",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884), [Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884)"
+        )
+    }
+
+    #[tokio::test]
+    async fn test_twitter_link() {
+        let test_message =
+            setup_test_message("https://x.com/testaccount/status/1814183041708990884");
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instagram_link() {
+        let test_message = setup_test_message("https://instagram.com/reel/foobar/?igsh=baz");
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[Instagram](https://ddinstagram.com/reel/foobar)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tiktok_link() {
+        let test_message = setup_test_message("https://vm.tiktok.com/foobar");
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[TikTok](https://vm.vxtiktok.com/foobar)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pixiv_link() {
+        let test_message = setup_test_message("https://www.pixiv.net/en/artworks/117847824");
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[Pixiv](https://phixiv.net/artworks/117847824)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixed_links_only() {
+        let test_message = setup_test_message(
+            "look at https://x.com/testaccount/status/1814183041708990884 and https://vm.tiktok.com/foobar",
+        );
+        let fixed = fix_message(&test_message).await.unwrap().unwrap();
+        assert_eq!(
+            fixed.links,
+            vec![
+                "[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884)",
+                "[TikTok](https://vm.vxtiktok.com/foobar)"
+            ]
+        );
+        assert_eq!(fixed.providers, vec!["twitter", "tiktok"]);
+        assert_eq!(
+            fixed.sources,
+            vec![
+                "https://x.com/testaccount/status/1814183041708990884",
+                "https://vm.tiktok.com/foobar"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repost_content() {
+        assert_eq!(reply_content(1, "fixed"), "<@1>: fixed");
+        assert_eq!(
+            companion_content(&["a".to_string(), "b".to_string()]),
+            "a\nb"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spoilered_link() {
+        let test_message = setup_test_message(
+            "look ||https://www.reddit.com/r/testcommunity/comments/something/|| here",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "look ||[Reddit](https://rxddit.com/r/testcommunity/comments/something/)|| here"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tracking_only_link() {
+        let content = "https://example.com/article?id=3&utm_source=share, read it";
+        // Cleaning links no rule rewrites is opt in
+        assert!(
+            fix_message(&setup_test_message(content))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let patterns = BUILT_PATTERNS.clone();
+        let prefilter = Prefilter::new(&patterns);
+        let fixed = rewrite(content, &patterns, &prefilter, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fixed.content, "https://example.com/article?id=3, read it");
+        assert_eq!(fixed.links, vec!["https://example.com/article?id=3"]);
+        assert_eq!(fixed.providers, vec![TRACKING_PROVIDER]);
+    }
+
+    #[test]
+    fn test_tracking_before_rules() {
+        let patterns = BUILT_PATTERNS.clone();
+        let prefilter = Prefilter::new(&patterns);
+        let content = "https://x.com/testaccount/status/1814183041708990884?s=20&t=abc https://example.com/?fbclid=1";
+        assert_eq!(
+            apply_patterns(content, &patterns, &prefilter, false)
+                .unwrap()
+                .unwrap(),
+            "[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884) https://example.com/?fbclid=1"
+        );
+        assert_eq!(
+            apply_patterns(content, &patterns, &prefilter, true)
+                .unwrap()
+                .unwrap(),
+            "[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884) https://example.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_code_block_link() {
+        let test_message = setup_test_message(
+            "```\nhttps://x.com/testaccount/status/1814183041708990884\n``` `https://vm.tiktok.com/foobar`",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_link() {
+        let test_message = setup_test_message(
+            "<https://x.com/testaccount/status/1814183041708990884> https://vm.tiktok.com/foobar",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "<https://x.com/testaccount/status/1814183041708990884> [TikTok](https://vm.vxtiktok.com/foobar)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spoilered_twitter_link() {
+        let test_message =
+            setup_test_message("||https://x.com/testaccount/status/1814183041708990884?s=20||");
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "||[Twitter](https://fxtwitter.com/testaccount/status/1814183041708990884)||"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reddit_link() {
+        let test_message = setup_test_message(
+            "https://www.reddit.com/r/testcommunity/comments/something/somethingAgain/",
+        );
+        let result = fix_links(&test_message).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            &result.unwrap().unwrap(),
+            "[Reddit](https://rxddit.com/r/testcommunity/comments/something/somethingAgain/)"
+        );
+    }
+}
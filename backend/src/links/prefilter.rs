@@ -0,0 +1,157 @@
+use super::BuiltPattern;
+use aho_corasick::AhoCorasick;
+use regex_syntax::{
+    ParserBuilder,
+    hir::literal::{ExtractKind, Extractor},
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// A single pass over a message that rules out patterns before any regex runs
+///
+/// Built from the literals every match of a pattern has to start with, like `https://x.com/`,
+/// all searched for at once. Patterns using fancy syntax or without a literal start can't be
+/// ruled out and always run, as does any pattern the prefilter wasn't built with
+#[derive(Debug, Default)]
+pub struct Prefilter {
+    literals: Option<AhoCorasick>,
+    /// The index of the pattern each literal belongs to
+    owners: Vec<usize>,
+    /// Pattern addresses mapped to their index, only for patterns that can be filtered
+    indices: HashMap<usize, usize>,
+    // Holding on to the patterns keeps their addresses from being reused by other rules
+    patterns: Vec<Arc<BuiltPattern>>,
+}
+
+/// Patterns that can't match the text the prefilter ran on
+#[derive(Debug)]
+pub struct RuledOut<'a> {
+    prefilter: &'a Prefilter,
+    found: Vec<bool>,
+}
+
+impl RuledOut<'_> {
+    pub fn contains(&self, pattern: &Arc<BuiltPattern>) -> bool {
+        self.prefilter
+            .indices
+            .get(&address(pattern))
+            .is_some_and(|&index| !self.found[index])
+    }
+}
+
+fn address(pattern: &Arc<BuiltPattern>) -> usize {
+    Arc::as_ptr(pattern) as usize
+}
+
+impl Prefilter {
+    pub fn new(patterns: &[Arc<BuiltPattern>]) -> Self {
+        let mut literals = Vec::new();
+        let mut owners = Vec::new();
+        let mut indices = HashMap::new();
+        for (index, built) in patterns.iter().enumerate() {
+            let Some(prefixes) = required_prefixes(built.pattern.as_str()) else {
+                continue;
+            };
+            indices.insert(address(built), index);
+            for prefix in prefixes {
+                literals.push(prefix);
+                owners.push(index);
+            }
+        }
+
+        Self {
+            literals: AhoCorasick::new(literals).ok(),
+            owners,
+            indices,
+            patterns: patterns.to_vec(),
+        }
+    }
+
+    /// A prefilter that rules nothing out
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn ruled_out(&self, text: &str) -> RuledOut<'_> {
+        let mut found = vec![false; self.patterns.len()];
+        if let Some(literals) = &self.literals {
+            for hit in literals.find_overlapping_iter(text) {
+                found[self.owners[hit.pattern().as_usize()]] = true;
+            }
+        }
+
+        RuledOut {
+            prefilter: self,
+            found,
+        }
+    }
+}
+
+fn required_prefixes(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    // Fails on lookarounds and backreferences, which only fancy_regex understands
+    let hir = ParserBuilder::new().build().parse(pattern).ok()?;
+    // Not optimized, that would shrink every url pattern down to the shared `https://`
+    let prefixes = Extractor::new().kind(ExtractKind::Prefix).extract(&hir);
+    let literals = prefixes.literals()?;
+    // An empty prefix matches everywhere so there is nothing to filter on
+    if literals.is_empty() || literals.iter().any(|l| l.as_bytes().is_empty()) {
+        return None;
+    }
+
+    Some(literals.iter().map(|l| l.as_bytes().to_vec()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fancy_regex::Regex;
+
+    fn built(pattern: &str) -> Arc<BuiltPattern> {
+        Arc::new(BuiltPattern::new(
+            "test".to_string(),
+            Regex::new(pattern).unwrap(),
+            String::new(),
+            vec![],
+        ))
+    }
+
+    #[test]
+    fn test_rules_out_other_hosts() {
+        let twitter = built(r"https?://(?:www\.)?(?:twitter\.com|x\.com)/([^/]+)");
+        let pixiv = built(r"https://(?:www\.)?pixiv\.net/artworks/(\d+)");
+        let prefilter = Prefilter::new(&[twitter.clone(), pixiv.clone()]);
+
+        let ruled_out = prefilter.ruled_out("look https://x.com/a/status/1");
+        assert!(!ruled_out.contains(&twitter));
+        assert!(ruled_out.contains(&pixiv));
+
+        let ruled_out = prefilter.ruled_out("no links here");
+        assert!(ruled_out.contains(&twitter));
+        assert!(ruled_out.contains(&pixiv));
+    }
+
+    #[test]
+    fn test_unfilterable_patterns() {
+        // Lookbehinds can't be parsed by regex-syntax and nothing has to come before `.*foo`
+        let lookbehind = built(r"(?<=a)b");
+        let anywhere = built(r".*foo");
+        let unknown = built(r"https://example\.com/");
+        let prefilter = Prefilter::new(&[lookbehind.clone(), anywhere.clone()]);
+
+        let ruled_out = prefilter.ruled_out("anything");
+        assert!(!ruled_out.contains(&lookbehind));
+        assert!(!ruled_out.contains(&anywhere));
+        assert!(!ruled_out.contains(&unknown));
+        assert!(!Prefilter::none().ruled_out("anything").contains(&unknown));
+    }
+
+    #[test]
+    fn test_case_insensitive_prefix() {
+        let pattern = built(r"(?i)https://example\.com/");
+        let prefilter = Prefilter::new(std::slice::from_ref(&pattern));
+        assert!(
+            !prefilter
+                .ruled_out("HTTPS://Example.com/a")
+                .contains(&pattern)
+        );
+    }
+}
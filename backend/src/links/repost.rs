@@ -0,0 +1,248 @@
+use super::{FixedMessage, companion_content, reply_content, webhook};
+use crate::music::sources::SOURCES;
+use anyhow::Result;
+use common::{context::get_context_wrapper, limits::guild_upload_limit};
+use database::{
+    links::{track_repost, untrack_repost},
+    models::{LinkFixMode, LinkReposts},
+};
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ChannelId, CreateAttachment, EditMessage, Error as SerenityError, HttpError, Message,
+    MessageId, StickerId,
+};
+
+/// Discord's error code for a message that doesn't exist anymore
+const UNKNOWN_MESSAGE: isize = 10008;
+
+/// The parts of the original message that have to survive the repost
+#[derive(Default)]
+pub struct Carryover {
+    pub attachments: Vec<CreateAttachment>,
+    pub stickers: Vec<StickerId>,
+    /// `false` when something could not be carried over, the original must be kept then
+    pub complete: bool,
+}
+
+impl Carryover {
+    /// Webhooks can neither reply nor send stickers
+    pub fn fits_webhook(&self, original: &Message) -> bool {
+        self.stickers.is_empty() && original.referenced_message.is_none()
+    }
+}
+
+/// Whether the bot may delete other peoples messages in the channel `message` was sent in
+pub async fn can_delete(message: &Message) -> bool {
+    let Some(guild_id) = message.guild_id else {
+        return false;
+    };
+    let ctx = get_context_wrapper();
+    let bot_id = ctx.cache.current_user().id;
+    let member = match guild_id.member(ctx, bot_id).await {
+        Ok(member) => member,
+        Err(e) => {
+            warn!("Failed to fetch own member in {}, {}", guild_id, e);
+            return false;
+        }
+    };
+
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return false;
+    };
+    // Threads inherit their permissions from the parent channel
+    let channel = guild.channels.get(&message.channel_id).or_else(|| {
+        guild
+            .threads
+            .iter()
+            .find(|thread| thread.id == message.channel_id)
+            .and_then(|thread| thread.parent_id)
+            .and_then(|parent| guild.channels.get(&parent))
+    });
+    channel.is_some_and(|channel| {
+        guild
+            .user_permissions_in(channel, &member)
+            .manage_messages()
+    })
+}
+
+/// The mode to fix a message in, given the guild's `mode`
+///
+/// Music downloads reply to the message with the music link, so in a music channel such a
+/// message is kept and only gets a companion
+pub fn mode_for(mode: LinkFixMode, content: &str, music_channel: bool) -> LinkFixMode {
+    if music_channel && SOURCES.find(content).is_some() {
+        return LinkFixMode::Suppress;
+    }
+    mode
+}
+
+/// Downloads attachments and picks out stickers from `message` so they can be re-sent
+///
+/// Attachments keep their filename, so `SPOILER_` files stay spoilered
+pub async fn collect_carryover(message: &Message) -> Carryover {
+    let mut carryover = Carryover {
+        complete: true,
+        ..Default::default()
+    };
+
+    let total_size: usize = message.attachments.iter().map(|a| a.size as usize).sum();
+    if total_size > guild_upload_limit(message.guild_id).await {
+        warn!(
+            "Attachments on {} exceed the upload limit, keeping the original",
+            message.id
+        );
+        carryover.complete = false;
+    } else {
+        for attachment in &message.attachments {
+            match attachment.download().await {
+                Ok(data) => {
+                    let mut file = CreateAttachment::bytes(data, attachment.filename.clone());
+                    if let Some(description) = &attachment.description {
+                        file = file.description(description);
+                    }
+                    carryover.attachments.push(file);
+                }
+                Err(e) => {
+                    warn!("Failed to download {}, {}", attachment.filename, e);
+                    carryover.complete = false;
+                }
+            }
+        }
+    }
+
+    // Bots can only send stickers that belong to the guild they are sending in
+    let usable = message.guild_id.and_then(|guild_id| {
+        get_context_wrapper()
+            .cache
+            .guild(guild_id)
+            .map(|guild| guild.stickers.keys().copied().collect::<Vec<_>>())
+    });
+    for sticker in &message.sticker_items {
+        if usable.as_ref().is_some_and(|ids| ids.contains(&sticker.id)) {
+            carryover.stickers.push(sticker.id);
+        } else {
+            carryover.complete = false;
+        }
+    }
+
+    carryover
+}
+
+/// Remembers which original message `sent` was made for so edits can follow it
+///
+/// A deleted original never gets edited, so without `original_kept` only webhook reposts are
+/// tracked, their owner can still delete them. Failing to track only loses edit handling, so
+/// it is logged instead of returned
+pub async fn track(sent: MessageId, original: &Message, mode: LinkFixMode, original_kept: bool) {
+    if !original_kept && mode != LinkFixMode::Webhook {
+        return;
+    }
+    if let Err(e) = track_repost(LinkReposts {
+        bot_message_id: sent.get() as i64,
+        channel_id: original.channel_id.get() as i64,
+        owner_id: original.author.id.get() as i64,
+        original_message_id: original_kept.then(|| original.id.get() as i64),
+        mode,
+    })
+    .await
+    {
+        warn!("Failed to track repost {} of {}, {}", sent, original.id, e);
+    }
+}
+
+/// Brings a tracked repost in line with an edited original
+///
+/// The repost is deleted when the edit removed every fixable link, reposts someone else
+/// already deleted stop being tracked
+pub async fn update_repost(repost: LinkReposts, fixed: Option<FixedMessage>) -> Result<()> {
+    match edit_tracked(&repost, fixed).await {
+        Err(e) if is_unknown_message(&e) => {
+            info!(
+                "Repost {} is already gone, no longer tracking it",
+                repost.bot_message_id
+            );
+            untrack_repost(repost.bot_message_id).await
+        }
+        result => result,
+    }
+}
+
+async fn edit_tracked(repost: &LinkReposts, fixed: Option<FixedMessage>) -> Result<()> {
+    let ctx = get_context_wrapper();
+    let channel_id = ChannelId::new(repost.channel_id as u64);
+    let message_id = MessageId::new(repost.bot_message_id as u64);
+
+    let Some(fixed) = fixed else {
+        delete_tracked(repost).await?;
+        info!("Deleted repost {} as its links were edited out", message_id);
+        return Ok(());
+    };
+
+    let content = match repost.mode {
+        LinkFixMode::Reply => reply_content(repost.owner_id as u64, &fixed.content),
+        LinkFixMode::Suppress => companion_content(&fixed.links),
+        LinkFixMode::Webhook => fixed.content,
+    };
+    match repost.mode {
+        LinkFixMode::Webhook => webhook::edit_message(channel_id, message_id, content).await?,
+        _ => {
+            channel_id
+                .edit_message(ctx, message_id, EditMessage::new().content(content))
+                .await?;
+        }
+    }
+    info!("Updated repost {} after an edit", message_id);
+
+    Ok(())
+}
+
+fn is_unknown_message(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<SerenityError>(),
+        Some(SerenityError::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.error.code == UNKNOWN_MESSAGE
+    )
+}
+
+/// Deletes a tracked repost however it was sent and stops tracking it
+pub async fn delete_tracked(repost: &LinkReposts) -> Result<()> {
+    let ctx = get_context_wrapper();
+    let channel_id = ChannelId::new(repost.channel_id as u64);
+    let message_id = MessageId::new(repost.bot_message_id as u64);
+
+    match repost.mode {
+        LinkFixMode::Webhook => webhook::delete_message(channel_id, message_id).await?,
+        _ => channel_id.delete_message(&ctx.http, message_id).await?,
+    }
+    untrack_repost(repost.bot_message_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_mode_for() {
+        let tweet = "https://x.com/testaccount/status/1814183041708990884";
+        let song = "https://youtu.be/xCMqBDWr-bk and https://x.com/testaccount/status/1";
+
+        // Other links in a music channel are fixed as usual
+        assert_eq!(
+            mode_for(LinkFixMode::Webhook, tweet, true),
+            LinkFixMode::Webhook
+        );
+        assert_eq!(
+            mode_for(LinkFixMode::Reply, tweet, true),
+            LinkFixMode::Reply
+        );
+        assert_eq!(
+            mode_for(LinkFixMode::Webhook, song, true),
+            LinkFixMode::Suppress
+        );
+        assert_eq!(
+            mode_for(LinkFixMode::Webhook, song, false),
+            LinkFixMode::Webhook
+        );
+    }
+}
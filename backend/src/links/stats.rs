@@ -0,0 +1,191 @@
+use chrono::{Days, NaiveDate, Utc};
+use database::{
+    models::LinkFixStats,
+    stats::{fetch_link_stats, prune_link_stats, record_link_fixes},
+};
+use log::{error, info};
+use poise::serenity_prelude::Message;
+use std::collections::HashMap;
+use tokio::{
+    task,
+    time::{self, Duration},
+};
+
+/// Days of stats kept before the daily prune removes them
+pub const RETENTION_DAYS: u64 = 365;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Totals for one guild over a period, sorted with the largest counts first
+#[derive(Debug, Default, PartialEq)]
+pub struct LinkStatsSummary {
+    pub total: i64,
+    pub providers: Vec<(String, i64)>,
+    pub posters: Vec<(u64, i64)>,
+}
+
+/// Counts every fixed link in `message` towards today, messages outside of guilds are ignored
+pub async fn record(message: &Message, providers: &[String]) -> anyhow::Result<()> {
+    let Some(guild_id) = message.guild_id else {
+        return Ok(());
+    };
+
+    let mut counts: HashMap<&str, i32> = HashMap::new();
+    for provider in providers {
+        *counts.entry(provider.as_str()).or_default() += 1;
+    }
+
+    let day = Utc::now().date_naive();
+    record_link_fixes(
+        counts
+            .into_iter()
+            .map(|(provider, count)| LinkFixStats {
+                guild_id: guild_id.get() as i64,
+                channel_id: message.channel_id.get() as i64,
+                provider: provider.to_owned(),
+                user_id: message.author.id.get() as i64,
+                day,
+                count,
+            })
+            .collect(),
+    )
+    .await
+}
+
+/// The first day included when looking back `days` days, `None` covers everything still stored
+pub fn since(days: Option<u64>) -> NaiveDate {
+    let days = days.unwrap_or(RETENTION_DAYS).min(RETENTION_DAYS);
+    Utc::now()
+        .date_naive()
+        .checked_sub_days(Days::new(days.saturating_sub(1)))
+        .unwrap_or(NaiveDate::MIN)
+}
+
+fn sorted<K: Ord>(totals: HashMap<K, i64>) -> Vec<(K, i64)> {
+    let mut totals = totals.into_iter().collect::<Vec<_>>();
+    totals.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+    totals
+}
+
+pub fn summarize(rows: &[LinkFixStats]) -> LinkStatsSummary {
+    let mut providers: HashMap<String, i64> = HashMap::new();
+    let mut posters: HashMap<u64, i64> = HashMap::new();
+    for row in rows {
+        *providers.entry(row.provider.clone()).or_default() += row.count as i64;
+        *posters.entry(row.user_id as u64).or_default() += row.count as i64;
+    }
+
+    LinkStatsSummary {
+        total: rows.iter().map(|row| row.count as i64).sum(),
+        providers: sorted(providers),
+        posters: sorted(posters),
+    }
+}
+
+pub async fn fetch_summary(guild_id: u64, since: NaiveDate) -> anyhow::Result<LinkStatsSummary> {
+    Ok(summarize(&fetch_link_stats(guild_id as i64, since).await?))
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+pub fn to_csv(rows: &[LinkFixStats]) -> String {
+    let mut csv = String::from("day,channel_id,provider,user_id,count\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            row.day,
+            row.channel_id,
+            csv_field(&row.provider),
+            row.user_id,
+            row.count
+        ));
+    }
+    csv
+}
+
+pub async fn export_csv(guild_id: u64, since: NaiveDate) -> anyhow::Result<String> {
+    Ok(to_csv(&fetch_link_stats(guild_id as i64, since).await?))
+}
+
+pub struct StatsManager;
+impl StatsManager {
+    /// Drops stats older than [`RETENTION_DAYS`] on startup and then once a day
+    pub fn start() -> Self {
+        info!("Spawning new stats manager");
+        task::spawn(async move {
+            loop {
+                match prune_link_stats(since(None)).await {
+                    Ok(0) => {}
+                    Ok(pruned) => info!("Pruned {} old link stat rows", pruned),
+                    Err(e) => error!("Failed to prune link stats, {}", e),
+                }
+                time::sleep(PRUNE_INTERVAL).await;
+            }
+        });
+
+        Self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn row(provider: &str, user_id: i64, count: i32) -> LinkFixStats {
+        LinkFixStats {
+            guild_id: 1,
+            channel_id: 2,
+            provider: provider.to_string(),
+            user_id,
+            day: NaiveDate::from_ymd_opt(2025, 3, 25).unwrap(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let rows = vec![
+            row("twitter", 10, 2),
+            row("pixiv", 11, 3),
+            row("twitter", 11, 1),
+            row("reddit", 12, 3),
+        ];
+        assert_eq!(
+            summarize(&rows),
+            LinkStatsSummary {
+                total: 9,
+                providers: vec![
+                    ("pixiv".to_string(), 3),
+                    ("reddit".to_string(), 3),
+                    ("twitter".to_string(), 3)
+                ],
+                posters: vec![(11, 4), (12, 3), (10, 2)],
+            }
+        );
+        assert_eq!(summarize(&[]), LinkStatsSummary::default());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        assert_eq!(
+            to_csv(&[row("twitter", 10, 2), row("my, \"rule\"", 11, 1)]),
+            "day,channel_id,provider,user_id,count\n\
+             2025-03-25,2,twitter,10,2\n\
+             2025-03-25,2,\"my, \"\"rule\"\"\",11,1\n"
+        );
+    }
+
+    #[test]
+    fn test_since() {
+        let today = Utc::now().date_naive();
+        assert_eq!(since(Some(1)), today);
+        assert_eq!(since(Some(7)), today - Days::new(6));
+        assert_eq!(since(None), since(Some(RETENTION_DAYS * 2)));
+    }
+}
@@ -0,0 +1,226 @@
+use fancy_regex::Regex;
+use log::warn;
+use serde::Deserialize;
+use std::{collections::HashMap, ops::Range};
+use url::Url;
+
+/// Toggled like a provider but off until enabled, controls cleaning links that no rule rewrites
+pub const TRACKING_PROVIDER: &str = "tracking";
+
+lazy_static! {
+    static ref URL_REGEX: Regex = Regex::new(r"https?://[^\s<>]+").expect("Regex should compile");
+    pub static ref TRACKING_FILTER: TrackingFilter =
+        TrackingFilter::from_json(include_str!("../../../tracking.json"))
+            .expect("tracking.json should parse according to tests");
+}
+
+#[derive(Deserialize)]
+struct LoadedJson {
+    global: Vec<String>,
+    domains: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParamMatcher {
+    Exact(String),
+    /// Written as `prefix_*` in `tracking.json`
+    Prefix(String),
+}
+
+impl ParamMatcher {
+    fn new(param: &str) -> Self {
+        match param.strip_suffix('*') {
+            Some(prefix) => ParamMatcher::Prefix(prefix.to_owned()),
+            None => ParamMatcher::Exact(param.to_owned()),
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            ParamMatcher::Exact(param) => key == param,
+            ParamMatcher::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Query parameters that only exist to track who shared a link
+#[derive(Debug)]
+pub struct TrackingFilter {
+    global: Vec<ParamMatcher>,
+    domains: HashMap<String, Vec<ParamMatcher>>,
+}
+
+impl TrackingFilter {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let loaded: LoadedJson = serde_json::from_str(json)?;
+
+        Ok(Self {
+            global: loaded.global.iter().map(|p| ParamMatcher::new(p)).collect(),
+            domains: loaded
+                .domains
+                .into_iter()
+                .map(|(domain, params)| {
+                    (
+                        domain,
+                        params.iter().map(|p| ParamMatcher::new(p)).collect(),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    fn is_tracking(&self, host: &str, key: &str) -> bool {
+        self.global.iter().any(|m| m.matches(key))
+            || self
+                .domains
+                .iter()
+                .filter(|(domain, _)| {
+                    host == domain.as_str() || host.ends_with(&format!(".{}", domain))
+                })
+                .any(|(_, params)| params.iter().any(|m| m.matches(key)))
+    }
+
+    /// Removes tracking parameters from `link`, returns `None` if there was nothing to remove
+    ///
+    /// Only the query is rebuilt, everything else is kept exactly as it was written
+    pub fn clean(&self, link: &str) -> Option<String> {
+        let parsed = match Url::parse(link) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Failed to parse {}, {}", link, e);
+                return None;
+            }
+        };
+        let host = parsed.host_str()?.to_lowercase();
+
+        let (before_fragment, fragment) = match link.split_once('#') {
+            Some((before, fragment)) => (before, Some(fragment)),
+            None => (link, None),
+        };
+        let (base, query) = before_fragment.split_once('?')?;
+
+        let pairs = query.split('&').filter(|pair| !pair.is_empty());
+        let kept = pairs
+            .clone()
+            .filter(|pair| {
+                let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+                !self.is_tracking(&host, key)
+            })
+            .collect::<Vec<_>>();
+        if kept.len() == pairs.count() {
+            return None;
+        }
+
+        let mut cleaned = base.to_owned();
+        if !kept.is_empty() {
+            cleaned.push('?');
+            cleaned.push_str(&kept.join("&"));
+        }
+        if let Some(fragment) = fragment {
+            cleaned.push('#');
+            cleaned.push_str(fragment);
+        }
+
+        Some(cleaned)
+    }
+}
+
+/// Byte ranges of every url in `text`, trailing punctuation is left out like Discord does
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    URL_REGEX
+        .find_iter(text)
+        .filter_map(Result::ok)
+        .map(|found| {
+            let mut end = found.end();
+            while let Some(last) = text[found.start()..end].chars().last() {
+                let unbalanced_paren = last == ')'
+                    && text[found.start()..end].matches(')').count()
+                        > text[found.start()..end].matches('(').count();
+                if !(matches!(last, '.' | ',' | ':' | ';' | '!' | '?' | '\'' | '"')
+                    || unbalanced_paren)
+                {
+                    break;
+                }
+                end -= last.len_utf8();
+            }
+            found.start()..end
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn clean(link: &str) -> Option<String> {
+        TRACKING_FILTER.clean(link)
+    }
+
+    #[test]
+    fn test_load_json() {
+        let filter = TrackingFilter::from_json(include_str!("../../../tracking.json")).unwrap();
+        assert!(
+            filter
+                .global
+                .contains(&ParamMatcher::Prefix("utm_".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_global_params() {
+        assert_eq!(
+            clean("https://example.com/a?utm_source=x&id=3&fbclid=abc").as_deref(),
+            Some("https://example.com/a?id=3")
+        );
+        assert_eq!(
+            clean("https://open.spotify.com/track/123?si=abc").as_deref(),
+            Some("https://open.spotify.com/track/123")
+        );
+    }
+
+    #[test]
+    fn test_domain_params() {
+        assert_eq!(
+            clean("https://x.com/a/status/1?s=20&t=abc").as_deref(),
+            Some("https://x.com/a/status/1")
+        );
+        // `s` and `t` are only trackers on twitter
+        assert_eq!(clean("https://example.com/search?s=20&t=abc"), None);
+        assert_eq!(
+            clean("https://mobile.twitter.com/a/status/1?s=20").as_deref(),
+            Some("https://mobile.twitter.com/a/status/1")
+        );
+    }
+
+    #[test]
+    fn test_keeps_everything_else() {
+        assert_eq!(clean("https://example.com/a?id=3"), None);
+        assert_eq!(clean("https://example.com/a"), None);
+        assert_eq!(
+            clean("https://example.com/a/?utm_medium=x#section").as_deref(),
+            Some("https://example.com/a/#section")
+        );
+        assert_eq!(
+            clean("https://example.com/a?flag&igsh=1").as_deref(),
+            Some("https://example.com/a?flag")
+        );
+    }
+
+    #[test]
+    fn test_find_urls() {
+        let text = "see https://example.com/a?id=1, (https://example.com/b_(c)) and https://example.com/d.";
+        let found = find_urls(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                "https://example.com/a?id=1",
+                "https://example.com/b_(c)",
+                "https://example.com/d"
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use super::FixedMessage;
+use crate::music::{DownloadError, download_video};
+use common::limits::guild_upload_limit;
+use database::links::{fetch_all_video_channels, set_video_channel};
+use futures::future::join_all;
+use log::{info, warn};
+use poise::serenity_prelude::{CreateAttachment, Message};
+use std::collections::HashSet;
+use tokio::sync::RwLock;
+
+/// Providers whose links often point at a video the mirror fails to embed
+pub const VIDEO_PROVIDERS: [&str; 3] = ["twitter", "tiktok", "instagram"];
+
+lazy_static! {
+    pub static ref VIDEO_CHANNELS: VideoChannels = VideoChannels::new();
+}
+
+/// Channels that get videos attached to fixed links, off everywhere else
+#[derive(Default)]
+pub struct VideoChannels {
+    channels: RwLock<HashSet<i64>>,
+}
+
+impl VideoChannels {
+    pub fn new() -> Self {
+        Self {
+            channels: RwLock::new(HashSet::new()),
+        }
+    }
+
+    pub async fn is_enabled(&self, channel_id: i64) -> bool {
+        self.channels.read().await.contains(&channel_id)
+    }
+
+    pub async fn set(&self, channel_id: i64, guild_id: i64, enabled: bool) -> anyhow::Result<()> {
+        set_video_channel(channel_id, guild_id, enabled).await?;
+        let mut guard = self.channels.write().await;
+        if enabled {
+            guard.insert(channel_id);
+        } else {
+            guard.remove(&channel_id);
+        }
+        info!("Set video downloads in {} to {}", channel_id, enabled);
+
+        Ok(())
+    }
+
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let channels = fetch_all_video_channels()
+            .await?
+            .into_iter()
+            .map(|c| c.channel_id)
+            .collect();
+
+        let mut guard = self.channels.write().await;
+        *guard = channels;
+        info!("Reloaded video channels");
+
+        Ok(())
+    }
+}
+
+/// Wraps the url in a fixed link with `<>` so Discord doesn't embed it next to the video
+fn suppress_embed(link: &str) -> String {
+    match link.split_once("](") {
+        Some((label, url)) if url.ends_with(')') && !url.starts_with('<') => {
+            format!("{}](<{}>)", label, &url[..url.len() - 1])
+        }
+        Some(_) => link.to_owned(),
+        None => format!("<{}>", link),
+    }
+}
+
+/// Downloads the videos behind fixed links in `message`, as many as fit in the upload after
+/// `used` bytes
+///
+/// Every video is downloaded at once, the ones that fit are kept in order. Links that got
+/// their video have the embed suppressed, everything else stays as it was
+pub async fn download_videos(
+    fixed: &mut FixedMessage,
+    message: &Message,
+    used: usize,
+) -> Vec<CreateAttachment> {
+    let wanted = (0..fixed.links.len())
+        .filter(|&i| VIDEO_PROVIDERS.contains(&fixed.providers[i].as_str()))
+        .collect::<Vec<_>>();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+
+    let mut budget = guild_upload_limit(message.guild_id)
+        .await
+        .saturating_sub(used);
+    let (user, channel) = (message.author.id.get(), message.channel_id.get());
+    let downloads = join_all(
+        wanted
+            .iter()
+            .map(|&i| download_video(&fixed.sources[i], budget, user, channel)),
+    )
+    .await;
+
+    let mut videos = Vec::new();
+    for (i, download) in wanted.into_iter().zip(downloads) {
+        match download {
+            Ok(data) if data.len() <= budget => {
+                budget -= data.len();
+                videos.push(CreateAttachment::bytes(
+                    data,
+                    format!("{}_{}.mp4", fixed.providers[i], videos.len() + 1),
+                ));
+                let suppressed = suppress_embed(&fixed.links[i]);
+                fixed.content = fixed.content.replacen(&fixed.links[i], &suppressed, 1);
+                fixed.links[i] = suppressed;
+            }
+            Ok(_) | Err(DownloadError::FileTooLarge) => {
+                info!("Video for {} does not fit the upload", fixed.sources[i]);
+            }
+            Err(e) => warn!("Failed to download video for {}, {}", fixed.sources[i], e),
+        }
+    }
+
+    videos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_suppress_embed() {
+        assert_eq!(
+            suppress_embed("[Twitter](https://fxtwitter.com/a/status/1)"),
+            "[Twitter](<https://fxtwitter.com/a/status/1>)"
+        );
+        assert_eq!(
+            suppress_embed("https://example.com/a"),
+            "<https://example.com/a>"
+        );
+        assert_eq!(
+            suppress_embed("[Twitter](<https://fxtwitter.com/a/status/1>)"),
+            "[Twitter](<https://fxtwitter.com/a/status/1>)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skips_other_providers() {
+        let mut fixed = FixedMessage {
+            content: "[Pixiv](https://phixiv.net/artworks/1)".to_string(),
+            links: vec!["[Pixiv](https://phixiv.net/artworks/1)".to_string()],
+            providers: vec!["pixiv".to_string()],
+            sources: vec!["https://www.pixiv.net/artworks/1".to_string()],
+        };
+
+        let message = Message::default();
+        assert!(download_videos(&mut fixed, &message, 0).await.is_empty());
+        assert_eq!(fixed.content, "[Pixiv](https://phixiv.net/artworks/1)");
+    }
+}
@@ -0,0 +1,213 @@
+use super::repost::{Carryover, delete_tracked, track};
+use anyhow::{Result, anyhow};
+use common::context::get_context_wrapper;
+use database::{
+    links::{delete_webhook, fetch_repost, fetch_webhook, store_webhook},
+    models::{LinkFixMode, LinkWebhooks},
+};
+use log::{info, warn};
+use poise::serenity_prelude::StatusCode;
+use poise::serenity_prelude::{
+    Channel, ChannelId, CreateAllowedMentions, CreateWebhook, EditWebhookMessage, ExecuteWebhook,
+    HttpError, Message, MessageId, Webhook,
+};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+const WEBHOOK_NAME: &str = "Midnight";
+// Discord rejects webhook usernames longer than this
+const MAX_USERNAME_LENGTH: usize = 80;
+
+lazy_static! {
+    pub static ref WEBHOOK_CACHE: WebhookCache = WebhookCache::new();
+}
+
+pub enum RepostDeletion {
+    Deleted,
+    NotOwner,
+    NotTracked,
+}
+
+/// One webhook per channel, created on first use and persisted so restarts reuse it
+#[derive(Default)]
+pub struct WebhookCache {
+    webhooks: Mutex<HashMap<ChannelId, Arc<Webhook>>>,
+}
+
+impl WebhookCache {
+    pub fn new() -> Self {
+        Self {
+            webhooks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the webhook for a channel, loading it from the database or creating it when needed
+    pub async fn get(&self, channel_id: ChannelId) -> Result<Arc<Webhook>> {
+        // Held for the whole lookup so concurrent messages can't create duplicate webhooks
+        let mut guard = self.webhooks.lock().await;
+        if let Some(webhook) = guard.get(&channel_id) {
+            return Ok(webhook.clone());
+        }
+
+        let ctx = get_context_wrapper();
+        let webhook = match fetch_webhook(channel_id.get() as i64).await? {
+            Some(stored) => match Webhook::from_url(&ctx.http, &stored.webhook_url).await {
+                Ok(webhook) => webhook,
+                Err(e) => {
+                    warn!("Stored webhook for {} is unusable, {}", channel_id, e);
+                    create_webhook(channel_id).await?
+                }
+            },
+            None => create_webhook(channel_id).await?,
+        };
+
+        let webhook = Arc::new(webhook);
+        guard.insert(channel_id, webhook.clone());
+        Ok(webhook)
+    }
+
+    /// Forgets a webhook that was deleted on Discord's side so the next call recreates it
+    pub async fn invalidate(&self, channel_id: ChannelId) -> Result<()> {
+        self.webhooks.lock().await.remove(&channel_id);
+        delete_webhook(channel_id.get() as i64).await
+    }
+}
+
+async fn create_webhook(channel_id: ChannelId) -> Result<Webhook> {
+    let ctx = get_context_wrapper();
+    let webhook = channel_id
+        .create_webhook(&ctx, CreateWebhook::new(WEBHOOK_NAME))
+        .await?;
+    store_webhook(LinkWebhooks {
+        channel_id: channel_id.get() as i64,
+        webhook_url: webhook.url()?,
+    })
+    .await?;
+    info!("Created link webhook for {}", channel_id);
+
+    Ok(webhook)
+}
+
+/// Webhooks belong to the parent channel, messages in threads are sent with `in_thread`
+async fn webhook_target(channel_id: ChannelId) -> Result<(ChannelId, Option<ChannelId>)> {
+    let ctx = get_context_wrapper();
+    match channel_id.to_channel(&ctx).await? {
+        Channel::Guild(channel) if channel.thread_metadata.is_some() => {
+            let parent = channel
+                .parent_id
+                .ok_or_else(|| anyhow!("Thread {} has no parent channel", channel_id))?;
+            Ok((parent, Some(channel_id)))
+        }
+        _ => Ok((channel_id, None)),
+    }
+}
+
+fn is_unknown_webhook(error: &poise::serenity_prelude::Error) -> bool {
+    matches!(
+        error,
+        poise::serenity_prelude::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.status_code == StatusCode::NOT_FOUND
+    )
+}
+
+fn display_name(message: &Message) -> String {
+    message
+        .member
+        .as_ref()
+        .and_then(|member| member.nick.clone())
+        .or_else(|| message.author.global_name.clone())
+        .unwrap_or_else(|| message.author.name.clone())
+        .chars()
+        .take(MAX_USERNAME_LENGTH)
+        .collect()
+}
+
+/// Reposts `message_content` through the channel webhook with the original authors name and avatar
+pub async fn webhook_handler(
+    message_content: String,
+    original: &Message,
+    carryover: &Carryover,
+) -> Result<()> {
+    let ctx = get_context_wrapper();
+    let (channel_id, thread_id) = webhook_target(original.channel_id).await?;
+
+    let mut builder = ExecuteWebhook::new()
+        .content(message_content)
+        .username(display_name(original))
+        .avatar_url(original.author.face())
+        .allowed_mentions(CreateAllowedMentions::new())
+        .add_files(carryover.attachments.clone());
+    if let Some(thread_id) = thread_id {
+        builder = builder.in_thread(thread_id);
+    }
+
+    let webhook = WEBHOOK_CACHE.get(channel_id).await?;
+    let sent = match webhook.execute(&ctx, true, builder.clone()).await {
+        Ok(sent) => sent,
+        Err(e) if is_unknown_webhook(&e) => {
+            warn!("Webhook for {} was deleted, recreating", channel_id);
+            WEBHOOK_CACHE.invalidate(channel_id).await?;
+            WEBHOOK_CACHE
+                .get(channel_id)
+                .await?
+                .execute(&ctx, true, builder)
+                .await?
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(sent) = sent {
+        track(sent.id, original, LinkFixMode::Webhook, !carryover.complete).await;
+    }
+
+    Ok(())
+}
+
+/// Deletes a tracked repost, only the author of the original message is allowed to
+pub async fn delete_repost(message: &Message, user_id: u64) -> Result<RepostDeletion> {
+    let Some(repost) = fetch_repost(message.id.get() as i64).await? else {
+        return Ok(RepostDeletion::NotTracked);
+    };
+    if repost.owner_id as u64 != user_id {
+        return Ok(RepostDeletion::NotOwner);
+    }
+
+    delete_tracked(&repost).await?;
+
+    Ok(RepostDeletion::Deleted)
+}
+
+/// Replaces the content of a message this bot sent through a channel webhook
+pub async fn edit_message(
+    channel_id: ChannelId,
+    message_id: MessageId,
+    content: String,
+) -> Result<()> {
+    let ctx = get_context_wrapper();
+    let (channel_id, thread_id) = webhook_target(channel_id).await?;
+    let mut builder = EditWebhookMessage::new().content(content);
+    if let Some(thread_id) = thread_id {
+        builder = builder.in_thread(thread_id);
+    }
+
+    WEBHOOK_CACHE
+        .get(channel_id)
+        .await?
+        .edit_message(&ctx, message_id, builder)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes a message this bot sent through a channel webhook
+pub async fn delete_message(channel_id: ChannelId, message_id: MessageId) -> Result<()> {
+    let ctx = get_context_wrapper();
+    let (channel_id, thread_id) = webhook_target(channel_id).await?;
+    WEBHOOK_CACHE
+        .get(channel_id)
+        .await?
+        .delete_message(&ctx.http, thread_id, message_id)
+        .await?;
+
+    Ok(())
+}
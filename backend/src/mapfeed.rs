@@ -507,7 +507,7 @@ pub async fn subscription_handler(
 }
 
 pub fn create_reply_with_sorted_beatmaps(mut beatmaps: BeatmapsetVec) -> CreateReply {
-    beatmaps.sort_by(|a, b| a.ranked_date_unix.cmp(&b.ranked_date_unix));
+    beatmaps.sort_by_key(|b| b.ranked_date_unix);
 
     CreateReply::default().ephemeral(true).embed(
         CreateEmbed::default()
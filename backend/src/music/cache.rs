@@ -0,0 +1,480 @@
+use super::{Song, env_number, format::AudioFormat, sources::Track};
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, sync::Mutex};
+
+const DEFAULT_DIR: &str = "cache/music";
+const DEFAULT_MAX_MEGABYTES: u64 = 1024;
+const DEFAULT_TTL_HOURS: u64 = 24 * 7;
+const AUDIO_EXTENSION: &str = "ogg";
+const METADATA_EXTENSION: &str = "json";
+
+lazy_static! {
+    pub static ref MUSIC_CACHE: AudioCache = AudioCache::new(CacheConfig::from_env());
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    /// Least recently used songs are dropped once the cache grows past this
+    pub max_bytes: u64,
+    /// How long a download is served before it is fetched again
+    pub ttl: Duration,
+}
+
+impl CacheConfig {
+    /// Reads `MUSIC_CACHE_DIR`, `MUSIC_CACHE_MAX_MB` and `MUSIC_CACHE_TTL_HOURS`
+    pub fn from_env() -> Self {
+        Self {
+            dir: std::env::var("MUSIC_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_DIR)),
+            max_bytes: env_number("MUSIC_CACHE_MAX_MB", DEFAULT_MAX_MEGABYTES) * 1024 * 1024,
+            ttl: Duration::from_secs(env_number("MUSIC_CACHE_TTL_HOURS", DEFAULT_TTL_HOURS) * 3600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    size: u64,
+    created: SystemTime,
+    last_used: SystemTime,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub songs: usize,
+    pub bytes: u64,
+}
+
+/// Downloaded songs kept on disk so links posted again don't need another download
+pub struct AudioCache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AudioCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Picks up the songs already on disk, using their modification time as the download time
+    pub async fn reload(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.config.dir).await?;
+
+        let mut entries = HashMap::new();
+        let mut dir = fs::read_dir(&self.config.dir).await?;
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension() != Some(OsStr::new(AUDIO_EXTENSION)) {
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(OsStr::to_str) else {
+                continue;
+            };
+            let metadata = file.metadata().await?;
+            let created = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+            entries.insert(
+                key.to_owned(),
+                Entry {
+                    size: metadata.len(),
+                    created,
+                    last_used: created,
+                },
+            );
+        }
+
+        info!("Loaded {} cached songs", entries.len());
+        *self.entries.lock().await = entries;
+        self.evict().await;
+
+        Ok(())
+    }
+
+    /// The cached song for `track`, as long as it fits in `max_size`
+    pub async fn get(&self, track: &Track, max_size: usize) -> Option<Song> {
+        let song = self.read(&cache_key(track), max_size).await;
+        match song {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        song
+    }
+
+    async fn read(&self, key: &str, max_size: usize) -> Option<Song> {
+        {
+            let mut entries = self.entries.lock().await;
+            let entry = entries.get_mut(key)?;
+            if self.is_expired(entry) {
+                entries.remove(key);
+                drop(entries);
+                self.remove_files(key).await;
+                return None;
+            }
+            // Transcoded for a guild with a higher upload limit, downloading again fits it
+            if entry.size > max_size as u64 {
+                return None;
+            }
+            entry.last_used = SystemTime::now();
+        }
+
+        let data = match fs::read(self.path(key, AUDIO_EXTENSION)).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to read cached song {}, {}", key, e);
+                self.entries.lock().await.remove(key);
+                return None;
+            }
+        };
+        let metadata = match fs::read(self.path(key, METADATA_EXTENSION)).await {
+            Ok(json) => serde_json::from_slice(&json).ok(),
+            Err(_) => None,
+        };
+
+        Some(Song::new(data, metadata))
+    }
+
+    /// Stores `song` for `track`, unless a larger download of it is cached already
+    ///
+    /// Songs are only made smaller to fit an upload limit, so the larger file is the better
+    /// one and stays around for guilds that can take it
+    pub async fn insert(&self, track: &Track, song: &Song) -> io::Result<()> {
+        let key = cache_key(track);
+        let size = song.data.len() as u64;
+        if size > self.config.max_bytes {
+            return Ok(());
+        }
+        if self
+            .entries
+            .lock()
+            .await
+            .get(&key)
+            .is_some_and(|entry| entry.size > size && !self.is_expired(entry))
+        {
+            return Ok(());
+        }
+
+        match &song.metadata {
+            Some(metadata) => {
+                let json = serde_json::to_vec(metadata)?;
+                write_file(&self.path(&key, METADATA_EXTENSION), &json).await?;
+            }
+            None => remove_file(&self.path(&key, METADATA_EXTENSION)).await,
+        }
+        write_file(&self.path(&key, AUDIO_EXTENSION), &song.data).await?;
+
+        let now = SystemTime::now();
+        self.entries.lock().await.insert(
+            key,
+            Entry {
+                size,
+                created: now,
+                last_used: now,
+            },
+        );
+        self.evict().await;
+
+        Ok(())
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().await;
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            songs: entries.len(),
+            bytes: entries.values().map(|e| e.size).sum(),
+        }
+    }
+
+    fn is_expired(&self, entry: &Entry) -> bool {
+        entry
+            .created
+            .elapsed()
+            .is_ok_and(|age| age > self.config.ttl)
+    }
+
+    /// Drops expired songs, then the least recently used ones until the cache fits its cap
+    async fn evict(&self) {
+        let evicted = {
+            let mut entries = self.entries.lock().await;
+            let mut evicted: Vec<String> = entries
+                .iter()
+                .filter(|(_, entry)| self.is_expired(entry))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &evicted {
+                entries.remove(key);
+            }
+
+            let mut total: u64 = entries.values().map(|e| e.size).sum();
+            if total > self.config.max_bytes {
+                let mut by_use: Vec<(String, Entry)> =
+                    entries.iter().map(|(k, e)| (k.clone(), *e)).collect();
+                by_use.sort_by_key(|(_, entry)| entry.last_used);
+                for (key, entry) in by_use {
+                    if total <= self.config.max_bytes {
+                        break;
+                    }
+                    entries.remove(&key);
+                    total -= entry.size;
+                    evicted.push(key);
+                }
+            }
+
+            evicted
+        };
+
+        for key in evicted {
+            self.remove_files(&key).await;
+        }
+    }
+
+    async fn remove_files(&self, key: &str) {
+        remove_file(&self.path(key, AUDIO_EXTENSION)).await;
+        remove_file(&self.path(key, METADATA_EXTENSION)).await;
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.config.dir.join(format!("{}.{}", key, extension))
+    }
+}
+
+/// `provider-id`, with anything that could be a problem in a filename escaped, clips get
+/// `+start-end` added since escaping never outputs `+`
+fn cache_key(track: &Track) -> String {
+    let mut key = format!("{}-{}", escape(&track.provider), escape(&track.id));
+    if let Some(clip) = track.clip {
+        key.push_str(&format!(
+            "+{}-{}",
+            clip.start,
+            clip.end.map(|end| end.to_string()).unwrap_or_default()
+        ));
+    }
+    if track.format != AudioFormat::Ogg {
+        key.push('.');
+        key.push_str(track.format.extension());
+    }
+    key
+}
+
+fn escape(part: &str) -> String {
+    part.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Writes next to `path` first so a half written song is never served
+async fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let partial = partial_path(path);
+    fs::write(&partial, data).await?;
+    fs::rename(&partial, path).await
+}
+
+/// A name next to `path` that no other write uses, even one to the same path
+fn partial_path(path: &Path) -> PathBuf {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(
+        ".{}.partial",
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(partial)
+}
+
+async fn remove_file(path: &Path) {
+    match fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}, {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::music::{SongMetadata, clip::Clip};
+    use pretty_assertions::assert_eq;
+
+    fn track(id: &str) -> Track {
+        Track {
+            provider: "soundcloud".to_string(),
+            id: id.to_string(),
+            url: format!("https://soundcloud.com/{}", id),
+            clip: None,
+            format: AudioFormat::Ogg,
+        }
+    }
+
+    fn new_cache(dir: &Path, max_bytes: u64, ttl: Duration) -> AudioCache {
+        AudioCache::new(CacheConfig {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            ttl,
+        })
+    }
+
+    #[test]
+    fn test_cache_key() {
+        assert_eq!(
+            cache_key(&track("artist/some-song")),
+            "soundcloud-artist%2Fsome%2Dsong"
+        );
+        assert_ne!(cache_key(&track("a/b")), cache_key(&track("a-b")));
+        let clipped = Track {
+            clip: Some(Clip {
+                start: 90,
+                end: None,
+            }),
+            ..track("a/b")
+        };
+        assert_eq!(cache_key(&clipped), "soundcloud-a%2Fb+90-");
+        let mp3 = Track {
+            format: AudioFormat::Mp3,
+            ..clipped
+        };
+        assert_eq!(cache_key(&mp3), "soundcloud-a%2Fb+90-.mp3");
+    }
+
+    #[tokio::test]
+    async fn test_hit_and_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = new_cache(dir.path(), 1024, Duration::from_secs(3600));
+        cache.reload().await.unwrap();
+
+        assert!(cache.get(&track("a/one"), 100).await.is_none());
+        let metadata = SongMetadata {
+            title: "One".to_string(),
+            ..Default::default()
+        };
+        cache
+            .insert(&track("a/one"), &Song::new(vec![1; 10], Some(metadata)))
+            .await
+            .unwrap();
+
+        let song = cache.get(&track("a/one"), 100).await.unwrap();
+        assert_eq!(song.get(), vec![1; 10]);
+        // Too big for a guild with a lower upload limit
+        assert!(cache.get(&track("a/one"), 5).await.is_none());
+        assert_eq!(
+            cache.stats().await,
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                songs: 1,
+                bytes: 10,
+            }
+        );
+
+        // Survives a restart, metadata included
+        let reloaded = new_cache(dir.path(), 1024, Duration::from_secs(3600));
+        reloaded.reload().await.unwrap();
+        let song = reloaded.get(&track("a/one"), 100).await.unwrap();
+        assert_eq!(song.metadata().map(|m| m.title.as_str()), Some("One"));
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = new_cache(dir.path(), 25, Duration::from_secs(3600));
+
+        for id in ["a/one", "a/two"] {
+            cache
+                .insert(&track(id), &Song::new(vec![0; 10], None))
+                .await
+                .unwrap();
+        }
+        assert!(cache.get(&track("a/one"), 100).await.is_some());
+        cache
+            .insert(&track("a/three"), &Song::new(vec![0; 10], None))
+            .await
+            .unwrap();
+
+        assert!(cache.get(&track("a/two"), 100).await.is_none());
+        assert!(cache.get(&track("a/one"), 100).await.is_some());
+        assert!(cache.get(&track("a/three"), 100).await.is_some());
+        assert!(
+            !cache
+                .path(&cache_key(&track("a/two")), AUDIO_EXTENSION)
+                .exists()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_songs_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = new_cache(dir.path(), 1024, Duration::ZERO);
+
+        cache
+            .insert(&track("a/one"), &Song::new(vec![0; 10], None))
+            .await
+            .unwrap();
+        assert!(cache.get(&track("a/one"), 100).await.is_none());
+        assert_eq!(cache.stats().await.songs, 0);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_the_larger_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = new_cache(dir.path(), 1024, Duration::from_secs(3600));
+
+        cache
+            .insert(&track("a/one"), &Song::new(vec![1; 20], None))
+            .await
+            .unwrap();
+        // Transcoded down for a guild with a lower upload limit
+        cache
+            .insert(&track("a/one"), &Song::new(vec![2; 10], None))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            cache.get(&track("a/one"), 100).await.unwrap().get(),
+            vec![1; 20]
+        );
+        cache
+            .insert(&track("a/one"), &Song::new(vec![3; 30], None))
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get(&track("a/one"), 100).await.unwrap().get(),
+            vec![3; 30]
+        );
+    }
+
+    #[test]
+    fn test_partial_path() {
+        let ogg = Path::new("cache/soundcloud-a.ogg");
+        let partial = |path| partial_path(path).to_string_lossy().into_owned();
+
+        // Each format keeps its own name, and two writes to one path never share a file
+        assert!(partial(ogg).starts_with("cache/soundcloud-a.ogg."));
+        assert!(
+            partial(Path::new("cache/soundcloud-a.mp3.ogg"))
+                .starts_with("cache/soundcloud-a.mp3.ogg.")
+        );
+        assert_ne!(partial(ogg), partial(ogg));
+    }
+}
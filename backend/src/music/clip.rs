@@ -0,0 +1,202 @@
+use super::{DownloadError, format_duration};
+use url::Url;
+
+/// Query parameters sites use for where playback starts
+const START_PARAMS: [&str; 3] = ["t", "start", "time_continue"];
+
+/// A part of a song in whole seconds, `end` is the end of the song when left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Clip {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl Clip {
+    pub fn new(start: u64, end: Option<u64>) -> Result<Self, DownloadError> {
+        if end.is_some_and(|end| end <= start) {
+            return Err(DownloadError::InvalidClip(
+                "The clip has to end after it starts",
+            ));
+        }
+
+        Ok(Self { start, end })
+    }
+
+    /// Reads the timestamps out of a link, like `?t=90`, `?t=1m30s&end=120` or `#t=1:30`
+    pub fn from_link(link: &str) -> Option<Self> {
+        let url = Url::parse(link).ok()?;
+
+        let mut start = None;
+        let mut end = None;
+        for (key, value) in url.query_pairs() {
+            if START_PARAMS.contains(&key.as_ref()) {
+                start = parse_timestamp(&value);
+            } else if key == "end" {
+                end = parse_timestamp(&value);
+            }
+        }
+        // SoundCloud puts the timestamp in the fragment
+        if let Some(fragment) = url.fragment().and_then(|f| f.strip_prefix("t=")) {
+            start = start.or_else(|| parse_timestamp(fragment));
+        }
+
+        let start = start.unwrap_or_default();
+        if start == 0 && end.is_none() {
+            return None;
+        }
+        Self::new(start, end).ok()
+    }
+
+    /// How many seconds of a `duration` second long song the clip covers
+    ///
+    /// Clips reaching past the end of the song are rejected, the end may only round up to
+    /// the next whole second
+    pub fn length(&self, duration: f64) -> Result<f64, DownloadError> {
+        if self.start as f64 >= duration {
+            return Err(DownloadError::InvalidClip(
+                "The clip starts after the song ends",
+            ));
+        }
+        if self.end.is_some_and(|end| end as f64 > duration.ceil()) {
+            return Err(DownloadError::InvalidClip(
+                "The clip ends after the song does",
+            ));
+        }
+        let end = self.end.map_or(duration, |end| (end as f64).min(duration));
+
+        Ok(end - self.start as f64)
+    }
+
+    /// ffmpeg output arguments that cut the clip out
+    pub(super) fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = vec!["-ss".to_string(), self.start.to_string()];
+        if let Some(end) = self.end {
+            args.extend(["-to".to_string(), end.to_string()]);
+        }
+        args
+    }
+
+    /// `1:30-2:00`, or `1:30-end` without an end
+    pub fn label(&self) -> String {
+        format!(
+            "{}-{}",
+            format_duration(self.start as f64),
+            self.end
+                .map_or("end".to_string(), |end| format_duration(end as f64))
+        )
+    }
+}
+
+/// Parses `90`, `90s`, `1m30s`, `1h2m3s`, `1:30` and `1:02:03` into seconds
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    if text.contains(':') {
+        let parts: Vec<&str> = text.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        return parts.iter().try_fold(0, |total: u64, part| {
+            total.checked_mul(60)?.checked_add(part.parse().ok()?)
+        });
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = number
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(unit)?
+            .checked_add(total)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = total.checked_add(number.parse().ok()?)?;
+    }
+
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("90"), Some(90));
+        assert_eq!(parse_timestamp("90s"), Some(90));
+        assert_eq!(parse_timestamp("1m30s"), Some(90));
+        assert_eq!(parse_timestamp("1h2m3s"), Some(3723));
+        assert_eq!(parse_timestamp("1:30"), Some(90));
+        assert_eq!(parse_timestamp("1:02:03"), Some(3723));
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("soon"), None);
+        assert_eq!(parse_timestamp("1:2:3:4"), None);
+        // Too large to count in seconds
+        assert_eq!(parse_timestamp("99999999999999999999"), None);
+        assert_eq!(parse_timestamp("999999999999999999h"), None);
+        assert_eq!(parse_timestamp("999999999999999999:0:0"), None);
+        assert_eq!(parse_timestamp("18446744073709551615s1"), None);
+    }
+
+    #[test]
+    fn test_from_link() {
+        assert_eq!(
+            Clip::from_link("https://youtu.be/xCMqBDWr-bk?si=abc&t=90"),
+            Some(Clip {
+                start: 90,
+                end: None
+            })
+        );
+        assert_eq!(
+            Clip::from_link("https://www.youtube.com/watch?v=xCMqBDWr-bk&t=1m30s&end=120"),
+            Some(Clip {
+                start: 90,
+                end: Some(120)
+            })
+        );
+        assert_eq!(
+            Clip::from_link("https://soundcloud.com/artist/song#t=1:30"),
+            Some(Clip {
+                start: 90,
+                end: None
+            })
+        );
+        assert_eq!(Clip::from_link("https://youtu.be/xCMqBDWr-bk?t=0"), None);
+        assert_eq!(Clip::from_link("https://youtu.be/xCMqBDWr-bk"), None);
+        // Ending before the start makes no sense, the whole song is downloaded then
+        assert_eq!(
+            Clip::from_link("https://youtu.be/xCMqBDWr-bk?t=90&end=30"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_length_and_label() {
+        let clip = Clip::new(90, Some(120)).unwrap();
+        assert_eq!(clip.length(300.0).unwrap(), 30.0);
+        assert_eq!(clip.length(119.5).unwrap(), 29.5);
+        assert!(clip.length(100.0).is_err());
+        assert!(clip.length(60.0).is_err());
+        assert!(Clip::new(90, None).unwrap().length(90.0).is_err());
+        assert_eq!(clip.label(), "1:30-2:00");
+        assert_eq!(Clip::new(3723, None).unwrap().label(), "1:02:03-end");
+        assert!(Clip::new(90, Some(90)).is_err());
+    }
+}
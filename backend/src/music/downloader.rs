@@ -0,0 +1,130 @@
+use super::{DownloadError, SongMetadata, env_number, run_process};
+use futures::future::BoxFuture;
+use std::sync::{Arc, PoisonError, RwLock};
+use tokio::time::Duration;
+
+const DEFAULT_PROGRAM: &str = "yt-dlp";
+/// Enough for the largest source at a slow few megabytes per second
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+/// yt-dlp's info JSON lists every format, so it can get fairly large
+const MAX_METADATA_SIZE: usize = 8 * 1024 * 1024;
+
+lazy_static! {
+    static ref DOWNLOADER: RwLock<Arc<dyn Downloader>> = RwLock::new(Arc::new(YtDlp::from_env()));
+}
+
+/// Fetches what is behind a link, outputs over `max_size` bytes are rejected with
+/// [`DownloadError::FileTooLarge`]
+pub trait Downloader: Send + Sync {
+    /// The best audio of a single track, in whatever format the site has it
+    fn audio<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<Vec<u8>, DownloadError>>;
+
+    fn metadata<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<SongMetadata, DownloadError>>;
+
+    /// The video as an mp4
+    fn video<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<Vec<u8>, DownloadError>>;
+}
+
+/// Downloads by running yt-dlp
+pub struct YtDlp {
+    program: String,
+    timeout: Duration,
+}
+
+impl YtDlp {
+    pub fn new(program: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            program: program.into(),
+            timeout,
+        }
+    }
+
+    /// Runs the binary at `YT_DLP_PATH`, or `yt-dlp` from the `PATH` when it isn't set,
+    /// giving each run `MUSIC_DOWNLOAD_TIMEOUT_SECS`
+    pub fn from_env() -> Self {
+        let program = std::env::var("YT_DLP_PATH").unwrap_or_else(|_| DEFAULT_PROGRAM.into());
+        let timeout = env_number("MUSIC_DOWNLOAD_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS);
+        Self::new(program, Duration::from_secs(timeout))
+    }
+
+    async fn run(&self, args: Vec<String>, max_size: usize) -> Result<Vec<u8>, DownloadError> {
+        run_process(&self.program, args, None, max_size, self.timeout).await
+    }
+}
+
+impl Downloader for YtDlp {
+    fn audio<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<Vec<u8>, DownloadError>> {
+        Box::pin(self.run(
+            vec![
+                "--no-playlist".into(),
+                "-o".into(),
+                "-".into(),
+                "-x".into(),
+                url.to_owned(),
+            ],
+            max_size,
+        ))
+    }
+
+    fn metadata<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<SongMetadata, DownloadError>> {
+        Box::pin(async move {
+            let output = self
+                .run(
+                    vec![
+                        "--dump-json".into(),
+                        "--no-playlist".into(),
+                        "--skip-download".into(),
+                        url.to_owned(),
+                    ],
+                    MAX_METADATA_SIZE,
+                )
+                .await?;
+
+            Ok(serde_json::from_slice(&output)?)
+        })
+    }
+
+    fn video<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<Vec<u8>, DownloadError>> {
+        Box::pin(self.run(
+            vec![
+                "-f".into(),
+                "best[ext=mp4]/mp4".into(),
+                "--max-filesize".into(),
+                max_size.to_string(),
+                "-o".into(),
+                "-".into(),
+                url.to_owned(),
+            ],
+            max_size,
+        ))
+    }
+}
+
+/// The downloader every song and video goes through
+pub fn downloader() -> Arc<dyn Downloader> {
+    DOWNLOADER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Replaces the downloader for everything downloaded from now on
+pub fn set_downloader(downloader: impl Downloader + 'static) {
+    *DOWNLOADER.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(downloader);
+}
@@ -0,0 +1,42 @@
+/// The file songs are sent as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    /// Whatever audio yt-dlp extracted, in an Ogg container when it had to be re-encoded
+    #[default]
+    Ogg,
+    Mp3,
+    M4a,
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    /// The ffmpeg encoder used when the audio has to be re-encoded
+    pub(super) fn encoder(self) -> &'static str {
+        match self {
+            AudioFormat::Ogg | AudioFormat::Opus => "libopus",
+            AudioFormat::Mp3 => "libmp3lame",
+            AudioFormat::M4a => "aac",
+        }
+    }
+
+    /// ffmpeg arguments that write this format to stdout
+    pub(super) fn muxer_args(self) -> Vec<String> {
+        let muxer: &[&str] = match self {
+            AudioFormat::Ogg => &["-f", "ogg"],
+            AudioFormat::Opus => &["-f", "opus"],
+            AudioFormat::Mp3 => &["-f", "mp3"],
+            // stdout can't be seeked, so the index has to be written up front
+            AudioFormat::M4a => &["-movflags", "frag_keyframe+empty_moov", "-f", "ipod"],
+        };
+        muxer.iter().map(|arg| arg.to_string()).collect()
+    }
+}
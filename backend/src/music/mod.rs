@@ -0,0 +1,985 @@
+use cache::MUSIC_CACHE;
+use clip::Clip;
+use common::limits::guild_upload_limit;
+use database::subscriptions::{ChannelType, SubscriptionMode, fetch_all_subscribed_channels};
+use downloader::downloader;
+use format::AudioFormat;
+use log::{error, info, warn};
+use queue::{DOWNLOAD_QUEUE, Queued};
+use serde::{Deserialize, Serialize};
+use serenity::all::{Colour, CreateEmbed, GuildId, Message};
+use sources::{Found, SOURCES, Track};
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+    process::{ExitStatus, Stdio},
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::{AcquireError, Mutex, OnceCell},
+    task::{self, JoinError},
+    time::{self, Duration, Instant, error::Elapsed},
+};
+
+/// The largest download still worth transcoding, the same as the highest boost tier allows
+const MAX_SOURCE_SIZE: usize = 100 * 1024 * 1024;
+const TRANSCODE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Room left for the Ogg container and bitrate overshoot when aiming at a file size
+const SIZE_HEADROOM: f64 = 0.92;
+/// Below this Opus sounds too bad to bother, the track is too long for the upload then
+const MIN_BITRATE: u32 = 16_000;
+const MAX_BITRATE: u32 = 160_000;
+const MAX_FILENAME_CHARS: usize = 120;
+/// Any further links in a message are ignored
+const MAX_SONGS_PER_MESSAGE: usize = 5;
+/// Discord allows 10 attachments per message
+const MAX_ATTACHMENTS: usize = 10;
+/// How much of the end of yt-dlp's stderr is kept for errors, the actual error is at the end
+const STDERR_TAIL: usize = 4096;
+/// ffprobe only prints the duration, anything longer is not a duration
+const PROBE_OUTPUT_LIMIT: usize = 256;
+const READ_CHUNK: usize = 16 * 1024;
+
+pub mod cache;
+pub mod clip;
+pub mod downloader;
+pub mod format;
+pub mod queue;
+pub mod sources;
+
+lazy_static! {
+    pub static ref CHANNEL_CACHE: ChannelCache = ChannelCache::new();
+}
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("{0}")]
+    IoError(#[from] io::Error),
+
+    #[error("{0}")]
+    JoinError(#[from] JoinError),
+
+    #[error("")]
+    FileTooLarge,
+
+    #[error("Download took too long, {0}")]
+    DownloadTimeout(#[from] Elapsed),
+
+    #[error("yt-dlp did not output anything")]
+    EmptyOutput,
+
+    #[error("{program} failed with {status}, {stderr}")]
+    Failed {
+        program: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("Could not read the duration of the audio")]
+    UnknownDuration,
+
+    #[error("Playlists and albums can't be downloaded, link a single track instead")]
+    Playlist(String),
+
+    #[error("Failed to parse yt-dlp metadata, {0}")]
+    InvalidMetadata(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    InvalidClip(&'static str),
+
+    #[error("You are downloading too much, try again in {0} seconds")]
+    UserRateLimited(u64),
+
+    #[error("This channel is downloading too much, try again in {0} seconds")]
+    ChannelRateLimited(u64),
+
+    #[error("Only {0} songs are downloaded per message")]
+    TooManySongs(usize),
+
+    #[error("The download queue was closed")]
+    QueueClosed(#[from] AcquireError),
+}
+
+impl DownloadError {
+    /// The same rate limit for the next song it turns away, `None` for anything else
+    fn rate_limit_again(&self) -> Option<Self> {
+        match self {
+            Self::UserRateLimited(wait) => Some(Self::UserRateLimited(*wait)),
+            Self::ChannelRateLimited(wait) => Some(Self::ChannelRateLimited(*wait)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ChannelCache {
+    initialized: OnceCell<()>,
+    channels: Mutex<HashSet<i64>>,
+}
+
+pub struct Song {
+    data: Vec<u8>,
+    metadata: Option<SongMetadata>,
+    clip: Option<Clip>,
+    format: AudioFormat,
+}
+
+/// The parts of yt-dlp's info JSON worth showing
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SongMetadata {
+    pub title: String,
+    /// Only set for music, [`SongMetadata::uploader`] is the fallback
+    pub artist: Option<String>,
+    pub uploader: Option<String>,
+    /// In seconds
+    pub duration: Option<f64>,
+    pub thumbnail: Option<String>,
+    pub webpage_url: Option<String>,
+}
+
+impl ChannelCache {
+    pub fn new() -> Self {
+        Self {
+            initialized: OnceCell::new(),
+            channels: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub async fn check(&self, id: i64) -> bool {
+        self.ensure_initialized().await;
+        let guard = self.channels.lock().await;
+        guard.contains(&id)
+    }
+
+    pub async fn update_cache(&self) {
+        if self.ensure_initialized().await {
+            match fetch_all_subscribed_channels(ChannelType::Music(SubscriptionMode::Subscribe))
+                .await
+            {
+                Ok(ids) => {
+                    let stored: HashSet<i64> = ids.into_iter().collect();
+
+                    let mut guard = self.channels.lock().await;
+                    if stored.is_empty() {
+                        guard.clear();
+                        return;
+                    }
+
+                    let new_ids: HashSet<i64> = stored.difference(&guard).cloned().collect();
+                    let removed_ids: HashSet<i64> = guard.difference(&stored).cloned().collect();
+
+                    new_ids.iter().for_each(|i| {
+                        guard.insert(*i);
+                    });
+                    removed_ids.iter().for_each(|i| {
+                        guard.remove(i);
+                    });
+                    info!("Updated music channel cache successfully")
+                }
+                Err(e) => {
+                    error!("Failed to update music channel cache, {}", e)
+                }
+            }
+        }
+    }
+
+    async fn populate(&self) {
+        let mut guard = self.channels.lock().await;
+        if let Ok(ids) =
+            fetch_all_subscribed_channels(ChannelType::Music(SubscriptionMode::Subscribe)).await
+        {
+            *guard = ids.iter().cloned().collect::<HashSet<i64>>()
+        }
+
+        info!("Music channel cache initialized");
+    }
+
+    async fn ensure_initialized(&self) -> bool {
+        if self.initialized.get().is_none() {
+            self.populate().await;
+            self.initialized.set(()).ok();
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl Song {
+    pub fn new(data: Vec<u8>, metadata: Option<SongMetadata>) -> Self {
+        Self {
+            data,
+            metadata,
+            clip: None,
+            format: AudioFormat::default(),
+        }
+    }
+
+    /// Marks the song as the clip and format that `track` asked for
+    pub fn for_track(mut self, track: &Track) -> Self {
+        self.clip = track.clip;
+        self.format = track.format;
+        self
+    }
+
+    pub fn get(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn metadata(&self) -> Option<&SongMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// `Artist - Title.ogg`, or `audio.ogg` when nothing is known about the song, with the
+    /// extension of the song's format
+    pub fn filename(&self) -> String {
+        let mut name = match &self.metadata {
+            Some(metadata) => match metadata.display_artist() {
+                Some(artist) => sanitize_filename(&format!("{} - {}", artist, metadata.title)),
+                None => sanitize_filename(&metadata.title),
+            },
+            None => String::new(),
+        };
+        if let (false, Some(clip)) = (name.is_empty(), self.clip) {
+            name = sanitize_filename(&format!("{} ({})", name, clip.label()));
+        }
+
+        if name.is_empty() {
+            name = "audio".to_string();
+        }
+        format!("{}.{}", name, self.format.extension())
+    }
+
+    pub fn embed(&self) -> Option<CreateEmbed> {
+        let metadata = self.metadata.as_ref()?;
+        let mut embed = CreateEmbed::default()
+            .title(&metadata.title)
+            .colour(Colour::new(0xfc4fca));
+        if let Some(url) = &metadata.webpage_url {
+            embed = embed.url(url);
+        }
+        if let Some(artist) = metadata.display_artist() {
+            embed = embed.field("Artist", artist, true);
+        }
+        if let Some(duration) = metadata.duration {
+            embed = embed.field("Duration", format_duration(duration), true);
+        }
+        if let Some(clip) = self.clip {
+            embed = embed.field("Clip", clip.label(), true);
+        }
+        if let Some(thumbnail) = &metadata.thumbnail {
+            embed = embed.thumbnail(thumbnail);
+        }
+
+        Some(embed)
+    }
+}
+
+impl SongMetadata {
+    /// YouTube Music uploads come from `Artist - Topic` channels, the suffix is dropped
+    pub fn display_artist(&self) -> Option<&str> {
+        self.artist
+            .as_deref()
+            .or(self.uploader.as_deref())
+            .map(|artist| artist.strip_suffix(" - Topic").unwrap_or(artist))
+            .filter(|artist| !artist.is_empty())
+    }
+
+    /// ffmpeg arguments that write the metadata into the file as tags
+    fn tag_args(&self) -> Vec<String> {
+        let mut tags = vec![format!("title={}", self.title)];
+        if let Some(artist) = self.display_artist() {
+            tags.push(format!("artist={}", artist));
+        }
+        if let Some(url) = &self.webpage_url {
+            tags.push(format!("comment={}", url));
+        }
+
+        tags.into_iter()
+            .flat_map(|tag| ["-metadata".to_string(), tag])
+            .collect()
+    }
+}
+
+/// Drops everything that is not allowed in filenames on common systems
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || r#"/\:*?"<>|"#.contains(c) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    cleaned
+        .trim_matches('.')
+        .chars()
+        .take(MAX_FILENAME_CHARS)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Seconds as `m:ss`, or `h:mm:ss` for anything an hour or longer
+fn format_duration(duration: f64) -> String {
+    let total = duration.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// A song found in a message, either cached, waiting for its turn in the [`DOWNLOAD_QUEUE`]
+/// or skipped
+pub struct PendingSong {
+    track: Track,
+    limit: usize,
+    state: Pending,
+}
+
+enum Pending {
+    Cached(Song),
+    Queued(Queued<'static>),
+    /// Left out of the message, finishing it only reports why
+    Skipped(DownloadError),
+}
+
+impl PendingSong {
+    fn skipped(track: Track, limit: usize, reason: DownloadError) -> Self {
+        Self {
+            track,
+            limit,
+            state: Pending::Skipped(reason),
+        }
+    }
+
+    /// The place in the download queue, 0 when the song is cached, skipped or downloads
+    /// right away
+    pub fn position(&self) -> usize {
+        match &self.state {
+            Pending::Cached(_) | Pending::Skipped(_) => 0,
+            Pending::Queued(queued) => queued.position,
+        }
+    }
+
+    /// Waits for a download slot and downloads the song
+    pub async fn finish(self) -> Result<Song, DownloadError> {
+        let queued = match self.state {
+            Pending::Cached(song) => return Ok(song),
+            Pending::Skipped(reason) => return Err(reason),
+            Pending::Queued(queued) => queued,
+        };
+        let _permit = queued.start().await?;
+
+        match download_audio(&self.track, self.limit).await {
+            Ok(song) => {
+                if let Err(e) = MUSIC_CACHE.insert(&self.track, &song).await {
+                    warn!("Failed to cache {}, {}", self.track.url, e);
+                }
+                Ok(song)
+            }
+            Err(e) => {
+                error!("Failed to download, link: {}, error: {}", self.track.url, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Finds every supported music link in `message` when it was sent in a music channel, cached
+/// songs are ready right away and the rest join the [`DOWNLOAD_QUEUE`]
+pub async fn music_link_handler(message: &Message) -> Result<Vec<PendingSong>, DownloadError> {
+    let found = SOURCES.find_all(&message.content);
+    if found.is_empty() || !CHANNEL_CACHE.check(message.channel_id.get() as i64).await {
+        return Ok(Vec::new());
+    }
+
+    request_songs(
+        found,
+        AudioFormat::default(),
+        message.guild_id,
+        message.author.id.get(),
+        message.channel_id.get(),
+    )
+    .await
+}
+
+/// Requests every track in `found` as `format` for `user` in `channel`, playlists are only
+/// an error when nothing else was found
+///
+/// Songs past [`MAX_SONGS_PER_MESSAGE`] or a rate limit come back skipped, so their
+/// reason shows up next to the ones that did download
+pub async fn request_songs(
+    found: Vec<Found>,
+    format: AudioFormat,
+    guild_id: Option<GuildId>,
+    user: u64,
+    channel: u64,
+) -> Result<Vec<PendingSong>, DownloadError> {
+    let mut tracks = Vec::new();
+    let mut playlist = None;
+    for found in found {
+        match found {
+            Found::Track(track) => tracks.push(Track { format, ..track }),
+            Found::Playlist(provider) => {
+                playlist.get_or_insert(provider);
+            }
+        }
+    }
+    if let (true, Some(provider)) = (tracks.is_empty(), playlist) {
+        info!("Refusing to download a {} playlist", provider);
+        return Err(DownloadError::Playlist(provider));
+    }
+    let limit = guild_upload_limit(guild_id).await;
+    let extra = tracks.split_off(tracks.len().min(MAX_SONGS_PER_MESSAGE));
+    if !extra.is_empty() {
+        info!(
+            "Only downloading the first {} of {} songs for {}",
+            MAX_SONGS_PER_MESSAGE,
+            tracks.len() + extra.len(),
+            user
+        );
+    }
+
+    let mut pending = Vec::new();
+    let mut limited = None;
+    for track in tracks {
+        if let Some(reason) = limited.as_ref().and_then(DownloadError::rate_limit_again) {
+            pending.push(PendingSong::skipped(track, limit, reason));
+            continue;
+        }
+        match request_song(track.clone(), limit, user, channel).await {
+            Ok(song) => pending.push(song),
+            Err(e) if pending.is_empty() => return Err(e),
+            Err(e) => {
+                info!("Skipping the remaining songs for {}, {}", user, e);
+                limited = e.rate_limit_again();
+                pending.push(PendingSong::skipped(track, limit, e));
+            }
+        }
+    }
+    pending.extend(extra.into_iter().map(|track| {
+        PendingSong::skipped(
+            track,
+            limit,
+            DownloadError::TooManySongs(MAX_SONGS_PER_MESSAGE),
+        )
+    }));
+
+    Ok(pending)
+}
+
+/// Serves `track` from the cache or queues its download for `user` in `channel`,
+/// as long as neither is rate limited
+pub async fn request_song(
+    track: Track,
+    limit: usize,
+    user: u64,
+    channel: u64,
+) -> Result<PendingSong, DownloadError> {
+    if let Some(song) = MUSIC_CACHE.get(&track, limit).await {
+        info!("Serving {} from the cache", track.url);
+        return Ok(PendingSong {
+            state: Pending::Cached(song.for_track(&track)),
+            track,
+            limit,
+        });
+    }
+
+    DOWNLOAD_QUEUE.admit(user, channel).await?;
+    Ok(PendingSong {
+        track,
+        limit,
+        state: Pending::Queued(DOWNLOAD_QUEUE.join()),
+    })
+}
+
+/// Downloads all `songs` at once, as far as the queue allows
+pub async fn finish_all(songs: Vec<PendingSong>) -> Vec<Result<Song, DownloadError>> {
+    futures::future::join_all(songs.into_iter().map(PendingSong::finish)).await
+}
+
+/// Splits `songs` into messages that stay within `max_size` and the attachment limit,
+/// keeping their order
+pub fn batch_songs(songs: Vec<Song>, max_size: usize) -> Vec<Vec<Song>> {
+    let mut batches: Vec<Vec<Song>> = Vec::new();
+    let mut batch_size = 0;
+    for song in songs {
+        match batches.last_mut() {
+            Some(batch) if batch.len() < MAX_ATTACHMENTS && batch_size + song.len() <= max_size => {
+                batch_size += song.len();
+                batch.push(song);
+            }
+            _ => {
+                batch_size = song.len();
+                batches.push(vec![song]);
+            }
+        }
+    }
+
+    batches
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("{} should be a number, using {}", name, default);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Runs `program` with `args`, feeding it `input`, and returns whatever it wrote to stdout
+///
+/// The process is killed as soon as its output goes over `max_size`, rejected with
+/// [`DownloadError::FileTooLarge`], or when it runs past `timeout`
+async fn run_process(
+    program: &str,
+    args: Vec<String>,
+    input: Option<Vec<u8>>,
+    max_size: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>, DownloadError> {
+    let deadline = Instant::now() + timeout;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        task::spawn(async move {
+            // Programs like ffprobe stop reading early, the broken pipe that causes is fine
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+    // Drained on its own so a chatty process never blocks on a full stderr pipe
+    let stderr = child.stderr.take();
+    let stderr = task::spawn(async move {
+        match stderr {
+            Some(stderr) => read_tail(stderr, STDERR_TAIL).await,
+            None => String::new(),
+        }
+    });
+    let Some(stdout) = child.stdout.take() else {
+        stop(program, &mut child).await;
+        return Err(DownloadError::EmptyOutput);
+    };
+
+    let finished = time::timeout_at(deadline, async {
+        let output = read_limited(stdout, max_size).await?;
+        let status = child.wait().await?;
+        Ok::<_, DownloadError>((output, status))
+    })
+    .await;
+
+    let (output, status) = match finished {
+        Ok(Ok(finished)) => finished,
+        Ok(Err(e)) => {
+            stop(program, &mut child).await;
+            return Err(e);
+        }
+        Err(e) => {
+            stop(program, &mut child).await;
+            return Err(DownloadError::DownloadTimeout(e));
+        }
+    };
+    if !status.success() {
+        return Err(DownloadError::Failed {
+            program: program.to_string(),
+            status,
+            stderr: stderr.await?,
+        });
+    }
+
+    Ok(output)
+}
+
+async fn stop(program: &str, child: &mut Child) {
+    if let Err(e) = child.kill().await {
+        warn!("Failed to kill {}, {}", program, e);
+    }
+}
+
+/// Reads `reader` to the end, giving up once more than `max_size` bytes come through
+async fn read_limited(
+    mut reader: impl AsyncRead + Unpin,
+    max_size: usize,
+) -> Result<Vec<u8>, DownloadError> {
+    let mut output = Vec::new();
+    let mut chunk = vec![0; READ_CHUNK];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(output);
+        }
+        if output.len() + read > max_size {
+            return Err(DownloadError::FileTooLarge);
+        }
+        output.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Reads `reader` to the end, keeping only the last `limit` bytes
+async fn read_tail(mut reader: impl AsyncRead + Unpin, limit: usize) -> String {
+    let mut tail = VecDeque::with_capacity(limit);
+    let mut chunk = vec![0; READ_CHUNK];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(read) => {
+                tail.extend(&chunk[..read]);
+                let excess = tail.len().saturating_sub(limit);
+                tail.drain(..excess);
+            }
+            Err(e) => {
+                warn!("Failed to read yt-dlp stderr, {}", e);
+                break;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(tail.make_contiguous())
+        .trim()
+        .to_string()
+}
+
+/// Downloads the audio for `track` along with its metadata, tagging the file with it
+///
+/// Clips are cut out of the full download, anything larger than `max_size` is transcoded
+/// down to fit
+async fn download_audio(track: &Track, max_size: usize) -> Result<Song, DownloadError> {
+    let id = &track.url;
+    info!("Fetching audio for {}", id);
+
+    let downloader = downloader();
+    // One after the other, so a queue permit never stands for more than one yt-dlp process
+    let metadata = match downloader.metadata(id).await {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            warn!("Failed to fetch metadata for {}, {}", id, e);
+            None
+        }
+    };
+    // Clips past the end of the song are turned down before anything is downloaded
+    if let (Some(clip), Some(duration)) = (track.clip, metadata.as_ref().and_then(|m| m.duration)) {
+        clip.length(duration)?;
+    }
+    let audio_data = downloader.audio(id, MAX_SOURCE_SIZE).await?;
+    // Cutting happens while encoding, so the clip goes in front of the tags
+    let mut options = track.clip.map(|c| c.ffmpeg_args()).unwrap_or_default();
+    options.extend(
+        metadata
+            .as_ref()
+            .map(SongMetadata::tag_args)
+            .unwrap_or_default(),
+    );
+
+    // Other formats always go through ffmpeg, the extracted audio is only sent as is for Ogg
+    let untouched = track.clip.is_none() && track.format == AudioFormat::Ogg;
+    if track.clip.is_some() || audio_data.len() <= max_size {
+        if untouched && options.is_empty() {
+            return Ok(Song::new(audio_data, metadata));
+        }
+        let remuxed = encode(
+            audio_data.clone(),
+            vec!["-c:a".into(), "copy".into()],
+            &options,
+            track.format,
+            max_size,
+        )
+        .await;
+        match remuxed {
+            Ok(data) => return Ok(Song::new(data, metadata).for_track(track)),
+            Err(e) if untouched => {
+                warn!("Failed to tag audio for {}, sending it untagged, {}", id, e);
+                return Ok(Song::new(audio_data, metadata));
+            }
+            Err(e) => info!("Could not convert {} without transcoding, {}", id, e),
+        }
+    }
+
+    info!(
+        "Audio for {} is {} bytes, transcoding to fit {}",
+        id,
+        audio_data.len(),
+        max_size
+    );
+    let mut duration = match metadata.as_ref().and_then(|m| m.duration) {
+        Some(duration) => duration,
+        None => probe_duration(audio_data.clone()).await?,
+    };
+    if let Some(clip) = track.clip {
+        duration = clip.length(duration)?;
+    }
+    let data = transcode_to_fit(audio_data, duration, &options, track.format, max_size).await?;
+
+    Ok(Song::new(data, metadata).for_track(track))
+}
+
+/// Re-encodes `audio` into `format` at whatever bitrate makes `duration` seconds fit in
+/// `max_size`
+async fn transcode_to_fit(
+    audio: Vec<u8>,
+    duration: f64,
+    options: &[String],
+    format: AudioFormat,
+    max_size: usize,
+) -> Result<Vec<u8>, DownloadError> {
+    let Some(bitrate) = target_bitrate(duration, max_size) else {
+        info!("{}s of audio does not fit {} bytes", duration, max_size);
+        return Err(DownloadError::FileTooLarge);
+    };
+
+    encode(
+        audio,
+        vec![
+            "-c:a".into(),
+            format.encoder().into(),
+            "-b:a".into(),
+            bitrate.to_string(),
+        ],
+        options,
+        format,
+        max_size,
+    )
+    .await
+}
+
+/// Runs `audio` through ffmpeg into a `format` file, using `codec` and extra output `options`
+/// like tags or where to cut
+async fn encode(
+    audio: Vec<u8>,
+    codec: Vec<String>,
+    options: &[String],
+    format: AudioFormat,
+    max_size: usize,
+) -> Result<Vec<u8>, DownloadError> {
+    let mut args: Vec<String> = vec![
+        "-hide_banner".into(),
+        "-loglevel".into(),
+        "error".into(),
+        "-i".into(),
+        "pipe:0".into(),
+        "-vn".into(),
+    ];
+    args.extend(codec);
+    args.extend_from_slice(options);
+    args.extend(format.muxer_args());
+    args.push("pipe:1".into());
+
+    run_process("ffmpeg", args, Some(audio), max_size, TRANSCODE_TIMEOUT).await
+}
+
+/// The duration of `audio` in seconds
+async fn probe_duration(audio: Vec<u8>) -> Result<f64, DownloadError> {
+    let output = run_process(
+        "ffprobe",
+        vec![
+            "-v".into(),
+            "error".into(),
+            "-show_entries".into(),
+            "format=duration".into(),
+            "-of".into(),
+            "default=noprint_wrappers=1:nokey=1".into(),
+            "-i".into(),
+            "pipe:0".into(),
+        ],
+        Some(audio),
+        PROBE_OUTPUT_LIMIT,
+        TRANSCODE_TIMEOUT,
+    )
+    .await?;
+
+    parse_duration(&output).ok_or(DownloadError::UnknownDuration)
+}
+
+fn parse_duration(output: &[u8]) -> Option<f64> {
+    String::from_utf8_lossy(output)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|duration| duration.is_finite() && *duration > 0.0)
+}
+
+/// The bitrate in bits per second that makes `duration` seconds of audio fit `max_size` bytes
+fn target_bitrate(duration: f64, max_size: usize) -> Option<u32> {
+    let bitrate = (max_size as f64 * 8.0 * SIZE_HEADROOM / duration) as u32;
+    (bitrate >= MIN_BITRATE).then(|| bitrate.min(MAX_BITRATE))
+}
+
+/// Downloads the video behind `url` as an mp4, as long as it is at most `max_size` bytes
+///
+/// Goes through the [`DOWNLOAD_QUEUE`] like songs do, counting against `user` and `channel`
+pub async fn download_video(
+    url: &str,
+    max_size: usize,
+    user: u64,
+    channel: u64,
+) -> Result<Vec<u8>, DownloadError> {
+    DOWNLOAD_QUEUE.admit(user, channel).await?;
+    let _permit = DOWNLOAD_QUEUE.join().start().await?;
+    info!("Fetching video for {}", url);
+
+    let video_data = downloader().video(url, max_size).await?;
+    if video_data.is_empty() {
+        return Err(DownloadError::EmptyOutput);
+    }
+
+    Ok(video_data)
+}
+
+#[cfg(test)]
+mod tests {
+    // Rust analyzer is stupid
+    #![allow(unused_imports, clippy::unwrap_used)]
+    use super::*;
+
+    fn metadata() -> SongMetadata {
+        serde_json::from_str(
+            r#"{
+                "id": "dQw4w9WgXcQ",
+                "title": "Never Gonna Give You Up",
+                "uploader": "Rick Astley - Topic",
+                "duration": 213,
+                "thumbnail": "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg",
+                "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                "formats": []
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_song_filename() {
+        let song = Song::new(vec![], Some(metadata()));
+        assert_eq!(song.filename(), "Rick Astley - Never Gonna Give You Up.ogg");
+        assert_eq!(Song::new(vec![], None).filename(), "audio.ogg");
+
+        let odd = SongMetadata {
+            title: "  AC/DC: Live?  ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(Song::new(vec![], Some(odd)).filename(), "AC DC Live.ogg");
+
+        let track = Track {
+            provider: "youtube".to_string(),
+            id: "dQw4w9WgXcQ".to_string(),
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            clip: Some(Clip::new(90, Some(120)).unwrap()),
+            format: AudioFormat::Mp3,
+        };
+        assert_eq!(
+            Song::new(vec![], Some(metadata()))
+                .for_track(&track)
+                .filename(),
+            "Rick Astley - Never Gonna Give You Up (1 30-2 00).mp3"
+        );
+        assert_eq!(
+            Song::new(vec![], None).for_track(&track).filename(),
+            "audio.mp3"
+        );
+    }
+
+    #[test]
+    fn test_tag_args() {
+        let metadata = SongMetadata {
+            artist: Some("Artist".to_string()),
+            ..metadata()
+        };
+        assert_eq!(
+            metadata.tag_args(),
+            [
+                "-metadata",
+                "title=Never Gonna Give You Up",
+                "-metadata",
+                "artist=Artist",
+                "-metadata",
+                "comment=https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_songs() {
+        let songs = [4, 4, 3, 9, 1].map(|size| Song::new(vec![0; size], None));
+        let batches: Vec<Vec<usize>> = batch_songs(songs.into(), 10)
+            .iter()
+            .map(|batch| batch.iter().map(Song::len).collect())
+            .collect();
+        assert_eq!(batches, [vec![4, 4], vec![3], vec![9, 1]]);
+
+        let songs = (0..12).map(|_| Song::new(vec![0; 1], None)).collect();
+        let batches: Vec<usize> = batch_songs(songs, 100).iter().map(Vec::len).collect();
+        assert_eq!(batches, [10, 2]);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(213.0), "3:33");
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(3725.0), "1:02:05");
+    }
+
+    #[test]
+    fn test_target_bitrate() {
+        let ten_mib = 10 * 1024 * 1024;
+        // 10 minutes into 10 MiB leaves about 128kbps
+        assert_eq!(target_bitrate(600.0, ten_mib), Some(128_625));
+        assert_eq!(target_bitrate(60.0, ten_mib), Some(MAX_BITRATE));
+        assert_eq!(target_bitrate(3.0 * 3600.0, ten_mib), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration(b"245.120000\n"), Some(245.12));
+        assert_eq!(parse_duration(b"N/A\n"), None);
+        assert_eq!(parse_duration(b""), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_limited() {
+        let data = vec![1u8; READ_CHUNK * 2 + 10];
+        assert_eq!(
+            read_limited(data.as_slice(), data.len()).await.unwrap(),
+            data
+        );
+        assert!(matches!(
+            read_limited(data.as_slice(), data.len() - 1).await,
+            Err(DownloadError::FileTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_tail() {
+        let stderr = b"[youtube] abc: Downloading webpage\nERROR: Video unavailable\n";
+        assert_eq!(
+            read_tail(stderr.as_slice(), 25).await,
+            "ERROR: Video unavailable"
+        );
+        assert_eq!(read_tail(b"".as_slice(), 23).await, "");
+    }
+}
@@ -0,0 +1,243 @@
+use super::{DownloadError, env_number};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::{
+    sync::{Mutex, Semaphore, SemaphorePermit},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_CONCURRENCY: u64 = 2;
+const DEFAULT_USER_LIMIT: u64 = 3;
+const DEFAULT_CHANNEL_LIMIT: u64 = 10;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    pub static ref DOWNLOAD_QUEUE: DownloadQueue = DownloadQueue::from_env();
+}
+
+/// Allows `limit` hits per key within a sliding `window`
+#[derive(Debug)]
+struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: HashMap<u64, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: HashMap::new(),
+        }
+    }
+
+    /// How long `key` has to wait before it is allowed again, `None` when it is allowed now
+    fn retry_after(&mut self, key: u64, now: Instant) -> Option<Duration> {
+        let hits = self.hits.get_mut(&key)?;
+        while hits
+            .front()
+            .is_some_and(|hit| now.duration_since(*hit) >= self.window)
+        {
+            hits.pop_front();
+        }
+        if hits.is_empty() {
+            self.hits.remove(&key);
+            return None;
+        }
+
+        if hits.len() < self.limit {
+            return None;
+        }
+        hits.front()
+            .map(|oldest| self.window.saturating_sub(now.duration_since(*oldest)))
+    }
+
+    /// Counts a hit for `key`, forgetting every key whose hits all left the window
+    fn record(&mut self, key: u64, now: Instant) {
+        self.hits.retain(|_, hits| {
+            hits.back()
+                .is_some_and(|last| now.duration_since(*last) < self.window)
+        });
+        self.hits.entry(key).or_default().push_back(now);
+    }
+}
+
+struct RateLimits {
+    users: RateLimiter,
+    channels: RateLimiter,
+}
+
+/// Limits how many yt-dlp downloads run at once and how often people can start them
+pub struct DownloadQueue {
+    permits: Semaphore,
+    /// Downloads that joined but haven't started yet
+    waiting: AtomicUsize,
+    limits: Mutex<RateLimits>,
+}
+
+/// A place in the [`DownloadQueue`], the download may start once [`Queued::start`] returns
+pub struct Queued<'a> {
+    queue: &'a DownloadQueue,
+    /// 0 when the download starts right away
+    pub position: usize,
+}
+
+impl DownloadQueue {
+    pub fn new(concurrency: usize, user_limit: usize, channel_limit: usize) -> Self {
+        Self {
+            permits: Semaphore::new(concurrency.max(1)),
+            waiting: AtomicUsize::new(0),
+            limits: Mutex::new(RateLimits {
+                users: RateLimiter::new(user_limit, RATE_LIMIT_WINDOW),
+                channels: RateLimiter::new(channel_limit, RATE_LIMIT_WINDOW),
+            }),
+        }
+    }
+
+    /// Reads `MUSIC_DOWNLOAD_CONCURRENCY`, `MUSIC_USER_LIMIT` and `MUSIC_CHANNEL_LIMIT`,
+    /// the limits are downloads per minute
+    pub fn from_env() -> Self {
+        Self::new(
+            env_number("MUSIC_DOWNLOAD_CONCURRENCY", DEFAULT_CONCURRENCY) as usize,
+            env_number("MUSIC_USER_LIMIT", DEFAULT_USER_LIMIT) as usize,
+            env_number("MUSIC_CHANNEL_LIMIT", DEFAULT_CHANNEL_LIMIT) as usize,
+        )
+    }
+
+    /// Counts a download for `user` in `channel`, unless either is over its limit
+    pub async fn admit(&self, user: u64, channel: u64) -> Result<(), DownloadError> {
+        let now = Instant::now();
+        let mut limits = self.limits.lock().await;
+        if let Some(wait) = limits.users.retry_after(user, now) {
+            return Err(DownloadError::UserRateLimited(wait.as_secs().max(1)));
+        }
+        if let Some(wait) = limits.channels.retry_after(channel, now) {
+            return Err(DownloadError::ChannelRateLimited(wait.as_secs().max(1)));
+        }
+        limits.users.record(user, now);
+        limits.channels.record(channel, now);
+
+        Ok(())
+    }
+
+    pub fn join(&self) -> Queued<'_> {
+        let ahead = self.waiting.fetch_add(1, Ordering::SeqCst);
+        // Free slots are taken by whoever is waiting first
+        let position = (ahead + 1).saturating_sub(self.permits.available_permits());
+
+        Queued {
+            queue: self,
+            position,
+        }
+    }
+}
+
+impl<'a> Queued<'a> {
+    /// Waits for a free download slot, which is held until the permit is dropped
+    pub async fn start(self) -> Result<SemaphorePermit<'a>, DownloadError> {
+        Ok(self.queue.permits.acquire().await?)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queue.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(limiter.retry_after(1, start), None);
+        limiter.record(1, start);
+        limiter.record(1, start + Duration::from_secs(10));
+        assert_eq!(
+            limiter.retry_after(1, start + Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+        // Other keys are counted on their own
+        assert_eq!(limiter.retry_after(2, start), None);
+        // The first hit left the window
+        assert_eq!(
+            limiter.retry_after(1, start + Duration::from_secs(60)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_forgets_old_keys() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        limiter.record(1, start);
+        limiter.record(2, start + Duration::from_secs(30));
+        limiter.record(3, start + Duration::from_secs(70));
+
+        assert_eq!(
+            limiter
+                .hits
+                .keys()
+                .copied()
+                .collect::<std::collections::BTreeSet<_>>(),
+            [2, 3].into()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admit() {
+        let queue = DownloadQueue::new(1, 2, 3);
+        queue.admit(1, 10).await.unwrap();
+        queue.admit(1, 10).await.unwrap();
+        assert!(matches!(
+            queue.admit(1, 10).await,
+            Err(DownloadError::UserRateLimited(_))
+        ));
+        queue.admit(2, 10).await.unwrap();
+        assert!(matches!(
+            queue.admit(3, 10).await,
+            Err(DownloadError::ChannelRateLimited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_queue_positions() {
+        let queue = DownloadQueue::new(1, 10, 10);
+
+        let first = queue.join();
+        assert_eq!(first.position, 0);
+        let running = first.start().await.unwrap();
+
+        let second = queue.join();
+        let third = queue.join();
+        assert_eq!((second.position, third.position), (1, 2));
+
+        drop(running);
+        let _running = second.start().await.unwrap();
+        assert_eq!(queue.join().position, 2);
+    }
+
+    #[tokio::test]
+    async fn test_free_slots_are_shared() {
+        let queue = DownloadQueue::new(2, 10, 10);
+        let positions: Vec<usize> = (0..3).map(|_| queue.join().position).collect();
+        // Each join is dropped right away, so they all see two free slots
+        assert_eq!(positions, [0, 0, 0]);
+
+        let joined: Vec<Queued> = (0..3).map(|_| queue.join()).collect();
+        assert_eq!(
+            joined.iter().map(|q| q.position).collect::<Vec<_>>(),
+            [0, 0, 1]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use super::{clip::Clip, format::AudioFormat};
+use fancy_regex::Regex;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+lazy_static! {
+    pub static ref SOURCES: Sources = Sources::from_json(include_str!("../../../sources.json"))
+        .expect("sources.json should parse according to tests");
+}
+
+#[derive(Deserialize)]
+struct LoadedJson {
+    provider: String,
+    pattern: String,
+    id: String,
+    url: String,
+    #[serde(default)]
+    playlists: Vec<String>,
+}
+
+/// A site yt-dlp can download single tracks from
+#[derive(Debug)]
+struct Source {
+    provider: String,
+    pattern: Regex,
+    /// Expanded with the captures of `pattern` into [`Track::id`]
+    id: String,
+    /// Expanded with the captures of `pattern` into [`Track::url`]
+    url: String,
+    /// Links to several tracks at once, like playlists or albums
+    playlists: Vec<Regex>,
+}
+
+/// A single track found in a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Track {
+    pub provider: String,
+    /// Stays the same for every link to the track, no matter how it was shared
+    pub id: String,
+    /// The canonical link handed to yt-dlp
+    pub url: String,
+    /// Only this part of the track is wanted
+    pub clip: Option<Clip>,
+    pub format: AudioFormat,
+}
+
+/// What a music link in a message turned out to be
+#[derive(Debug, PartialEq, Eq)]
+pub enum Found {
+    Track(Track),
+    /// A playlist or album from the named provider
+    Playlist(String),
+}
+
+#[derive(Debug)]
+pub struct Sources(Vec<Source>);
+
+impl Sources {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let loaded: Vec<LoadedJson> = serde_json::from_str(json)?;
+
+        let sources = loaded
+            .into_iter()
+            .map(|source| {
+                Ok(Source {
+                    provider: source.provider,
+                    pattern: Regex::new(&source.pattern)?,
+                    id: source.id,
+                    url: source.url,
+                    playlists: source
+                        .playlists
+                        .iter()
+                        .map(|p| Regex::new(p).map_err(Box::new))
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self(sources))
+    }
+
+    /// Finds the first track or playlist linked in `content`
+    pub fn find(&self, content: &str) -> Option<Found> {
+        self.find_all(content).into_iter().next()
+    }
+
+    /// Every track and playlist linked in `content` in the order they appear, tracks linked
+    /// more than once are only listed the first time
+    pub fn find_all(&self, content: &str) -> Vec<Found> {
+        let mut found = Vec::new();
+        for source in &self.0 {
+            // Playlist links often look like tracks too, so they are found first
+            let mut playlists = Vec::new();
+            for playlist in &source.playlists {
+                for found in playlist.find_iter(content) {
+                    match found {
+                        Ok(found) => playlists.push(found.range()),
+                        Err(e) => warn!("Failed to match {} playlists, {}", source.provider, e),
+                    }
+                }
+            }
+            found.extend(
+                playlists
+                    .iter()
+                    .map(|range| (range.start, Found::Playlist(source.provider.clone()))),
+            );
+
+            for captures in source.pattern.captures_iter(content) {
+                let captures = match captures {
+                    Ok(captures) => captures,
+                    Err(e) => {
+                        warn!("Failed to match {} tracks, {}", source.provider, e);
+                        break;
+                    }
+                };
+                let Some(whole) = captures.get(0) else {
+                    continue;
+                };
+                if playlists.iter().any(|range| range.contains(&whole.start())) {
+                    continue;
+                }
+
+                let mut id = String::new();
+                captures.expand(&source.id, &mut id);
+                let mut url = String::new();
+                captures.expand(&source.url, &mut url);
+                // Timestamps come after the part the pattern matches
+                let link_end = content[whole.end()..]
+                    .find(|c: char| c.is_whitespace() || c == '>' || c == ')')
+                    .map_or(content.len(), |i| whole.end() + i);
+                found.push((
+                    whole.start(),
+                    Found::Track(Track {
+                        provider: source.provider.clone(),
+                        id,
+                        url,
+                        clip: Clip::from_link(&content[whole.start()..link_end]),
+                        format: AudioFormat::default(),
+                    }),
+                ));
+            }
+        }
+        found.sort_by_key(|(start, _)| *start);
+
+        let mut seen = HashSet::new();
+        found
+            .into_iter()
+            .map(|(_, found)| found)
+            .filter(|found| match found {
+                Found::Track(track) => {
+                    seen.insert((track.provider.clone(), track.id.clone(), track.clip))
+                }
+                Found::Playlist(_) => true,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn track(content: &str) -> Track {
+        match SOURCES.find(content) {
+            Some(Found::Track(track)) => track,
+            other => panic!("Expected a track in {}, found {:?}", content, other),
+        }
+    }
+
+    #[test]
+    fn test_sources_parse() {
+        Sources::from_json(include_str!("../../../sources.json")).unwrap();
+    }
+
+    macro_rules! regex_test {
+        ($name:ident, $input:expr, $expected_capture:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(track($input).id, $expected_capture);
+            }
+        };
+    }
+
+    regex_test!(
+        test_www,
+        "https://www.youtube.com/watch?v=HOz-9FzIDf0",
+        "HOz-9FzIDf0"
+    );
+
+    regex_test!(
+        test_music,
+        "https://music.youtube.com/watch?v=lUQjaC5IaMA&si=uATM_kEIlpWDfwOI",
+        "lUQjaC5IaMA"
+    );
+
+    regex_test!(
+        test_shortened,
+        "https://youtu.be/xCMqBDWr-bk?si=BnST6uCCjEZ7uJpN",
+        "xCMqBDWr-bk"
+    );
+
+    #[test]
+    fn test_other_sources() {
+        assert_eq!(
+            track("listen https://soundcloud.com/artist-name/track-name?in=someone/sets/mix"),
+            Track {
+                provider: "soundcloud".to_string(),
+                id: "artist-name/track-name".to_string(),
+                url: "https://soundcloud.com/artist-name/track-name".to_string(),
+                clip: None,
+                format: AudioFormat::Ogg,
+            }
+        );
+        assert_eq!(
+            track("https://artist.bandcamp.com/track/some-song").url,
+            "https://artist.bandcamp.com/track/some-song"
+        );
+        assert_eq!(
+            track("https://nico.ms/sm9").url,
+            "https://www.nicovideo.jp/watch/sm9"
+        );
+        assert_eq!(
+            track("https://youtu.be/xCMqBDWr-bk?t=90").clip,
+            Some(Clip {
+                start: 90,
+                end: None
+            })
+        );
+        // Shared links point at the same track as the canonical one
+        assert_eq!(
+            track("https://youtu.be/xCMqBDWr-bk"),
+            track("https://www.youtube.com/watch?v=xCMqBDWr-bk")
+        );
+    }
+
+    #[test]
+    fn test_playlists() {
+        for link in [
+            "https://www.youtube.com/playlist?list=PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG",
+            "https://soundcloud.com/artist-name/sets/an-album",
+            "https://artist.bandcamp.com/album/an-album",
+            "https://www.nicovideo.jp/mylist/12345",
+        ] {
+            assert!(
+                matches!(SOURCES.find(link), Some(Found::Playlist(_))),
+                "{} should be a playlist",
+                link
+            );
+        }
+    }
+
+    #[test]
+    fn test_first_link_wins() {
+        assert_eq!(
+            SOURCES.find(
+                "https://artist.bandcamp.com/album/an-album then https://youtu.be/xCMqBDWr-bk"
+            ),
+            Some(Found::Playlist("bandcamp".to_string()))
+        );
+        assert_eq!(
+            track("https://youtu.be/xCMqBDWr-bk then https://artist.bandcamp.com/album/an-album")
+                .provider,
+            "youtube"
+        );
+        assert_eq!(
+            SOURCES.find("https://example.com/watch?v=xCMqBDWr-bk"),
+            None
+        );
+    }
+}
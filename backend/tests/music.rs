@@ -0,0 +1,168 @@
+#![allow(clippy::unwrap_used)]
+use backend::music::{
+    self, CHANNEL_CACHE, DownloadError, Song,
+    downloader::{YtDlp, downloader, set_downloader},
+    music_link_handler,
+    sources::SOURCES,
+};
+use database::subscriptions::{ChannelType, SubscriptionMode, channel_subscription_handler};
+use poise::serenity_prelude::{ChannelId, Message, UserId};
+use tokio::{sync::OnceCell, time::Duration};
+
+const MUSIC_CHANNEL: u64 = 1_000_000_000_000_000_001;
+
+static SETUP: OnceCell<()> = OnceCell::const_new();
+
+/// Points the downloader at the fake yt-dlp and subscribes [`MUSIC_CHANNEL`], once per process
+async fn setup() {
+    SETUP
+        .get_or_init(|| async {
+            std::env::set_var("DATABASE_URL", "postgres://postgres@127.0.0.1:5432/testing");
+            // Nothing fits in an empty cache, so every test really downloads
+            std::env::set_var("MUSIC_CACHE_MAX_MB", "0");
+            database::core::initialise()
+                .await
+                .expect("Failed to initialise database");
+
+            let channel = MUSIC_CHANNEL as i64;
+            channel_subscription_handler(
+                channel,
+                ChannelType::Music(SubscriptionMode::Unsubscribe),
+            )
+            .await
+            .unwrap();
+            channel_subscription_handler(channel, ChannelType::Music(SubscriptionMode::Subscribe))
+                .await
+                .unwrap();
+            // The channel cache loads from the database on first use
+            assert!(CHANNEL_CACHE.check(channel).await);
+
+            let fake = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-yt-dlp");
+            set_downloader(YtDlp::new(fake, Duration::from_millis(500)));
+        })
+        .await;
+}
+
+/// Every test posts as a different user so the rate limits don't get in the way
+fn message(content: &str, author: u64, channel: u64) -> Message {
+    let mut message = Message::default();
+    message.content = content.to_string();
+    message.author.id = UserId::new(author);
+    message.channel_id = ChannelId::new(channel);
+    message
+}
+
+async fn download(content: &str, author: u64) -> Vec<Result<Song, DownloadError>> {
+    setup().await;
+    let pending = music_link_handler(&message(content, author, MUSIC_CHANNEL))
+        .await
+        .unwrap();
+    music::finish_all(pending).await
+}
+
+#[tokio::test]
+async fn test_download() {
+    let mut results = download("https://youtu.be/succeeds000", 1).await;
+
+    assert_eq!(results.len(), 1);
+    let song = results.remove(0).unwrap();
+    assert_eq!(song.filename(), "audio.ogg");
+    assert_eq!(song.get(), b"fake audio");
+}
+
+#[tokio::test]
+async fn test_timeout() {
+    let results = download("https://youtu.be/sleeps00000", 2).await;
+
+    assert!(matches!(
+        results.as_slice(),
+        [Err(DownloadError::DownloadTimeout(_))]
+    ));
+}
+
+#[tokio::test]
+async fn test_too_large() {
+    let results = download("https://youtu.be/toolarge000", 3).await;
+
+    assert!(matches!(
+        results.as_slice(),
+        [Err(DownloadError::FileTooLarge)]
+    ));
+}
+
+#[tokio::test]
+async fn test_output_over_limit() {
+    setup().await;
+    let pidfile = std::env::temp_dir().join(format!("fake-yt-dlp-{}", std::process::id()));
+    let url = format!("https://youtu.be/endless0000?pidfile={}", pidfile.display());
+
+    let result = downloader().audio(&url, 1024).await;
+
+    assert!(matches!(result, Err(DownloadError::FileTooLarge)));
+    let pid = std::fs::read_to_string(&pidfile).unwrap();
+    std::fs::remove_file(&pidfile).unwrap();
+    // The process is killed and reaped before the error comes back
+    assert!(!std::path::Path::new(&format!("/proc/{}", pid.trim())).exists());
+}
+
+#[tokio::test]
+async fn test_failure() {
+    let results = download("https://youtu.be/fails000000", 4).await;
+
+    match results.as_slice() {
+        [Err(DownloadError::Failed { stderr, .. })] => {
+            assert!(stderr.contains("Video unavailable"), "{}", stderr)
+        }
+        _ => panic!("Expected yt-dlp to fail"),
+    }
+}
+
+#[tokio::test]
+async fn test_several_links() {
+    let results = download(
+        "https://youtu.be/succeeds001 and https://youtu.be/fails000001",
+        5,
+    )
+    .await;
+
+    assert!(matches!(
+        results.as_slice(),
+        [Ok(_), Err(DownloadError::Failed { .. })]
+    ));
+}
+
+#[tokio::test]
+async fn test_skipped_songs_say_why() {
+    setup().await;
+    let content = (0..7)
+        .map(|i| format!("https://youtu.be/succeeds{:03}", i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    // Outside the shared music channel, so only the user limit of 3 gets in the way
+    let pending = music::request_songs(SOURCES.find_all(&content), Default::default(), None, 7, 2)
+        .await
+        .unwrap();
+    let results = music::finish_all(pending).await;
+
+    assert_eq!(results.len(), 7);
+    assert!(results[..3].iter().all(Result::is_ok));
+    assert!(matches!(
+        results[3..].iter().collect::<Vec<_>>().as_slice(),
+        [
+            Err(DownloadError::UserRateLimited(_)),
+            Err(DownloadError::UserRateLimited(_)),
+            Err(DownloadError::TooManySongs(5)),
+            Err(DownloadError::TooManySongs(5)),
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn test_other_channels_are_ignored() {
+    setup().await;
+    let pending = music_link_handler(&message("https://youtu.be/succeeds000", 6, 1))
+        .await
+        .unwrap();
+
+    assert!(pending.is_empty());
+}
@@ -0,0 +1,62 @@
+use crate::{Context, Error};
+use backend::links::{
+    OPT_OUT_CACHE,
+    webhook::{RepostDeletion, delete_repost},
+};
+use poise::{CreateReply, serenity_prelude as serenity};
+use tracing::info;
+
+#[poise::command(slash_command, subcommands("optout", "optin"))]
+pub async fn links(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Stop the bot from fixing links in your messages, in every server
+#[poise::command(slash_command, category = "Links")]
+pub async fn optout(ctx: Context<'_>) -> Result<(), Error> {
+    OPT_OUT_CACHE
+        .set(ctx.author().id.get() as i64, true)
+        .await?;
+    info!("User {} opted out of link fixing", ctx.author().tag());
+
+    let builder = CreateReply::default()
+        .content("Your messages will no longer have their links fixed")
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Let the bot fix links in your messages again
+#[poise::command(slash_command, category = "Links")]
+pub async fn optin(ctx: Context<'_>) -> Result<(), Error> {
+    OPT_OUT_CACHE
+        .set(ctx.author().id.get() as i64, false)
+        .await?;
+    info!("User {} opted in to link fixing", ctx.author().tag());
+
+    let builder = CreateReply::default()
+        .content("Your messages will have their links fixed again")
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Deletes a fixed link that was reposted on your behalf
+#[poise::command(context_menu_command = "Delete fixed message", guild_only)]
+pub async fn delete_fixed(ctx: Context<'_>, message: serenity::Message) -> Result<(), Error> {
+    let content = match delete_repost(&message, ctx.author().id.get()).await? {
+        RepostDeletion::Deleted => {
+            info!("User {} deleted repost {}", ctx.author().tag(), message.id);
+            "Deleted the message"
+        }
+        RepostDeletion::NotOwner => "You are not the owner of this message!",
+        RepostDeletion::NotTracked => "This is not a fixed link message",
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
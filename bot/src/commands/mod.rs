@@ -1,7 +1,11 @@
 pub mod cat;
+pub mod links;
 pub mod mapfeed;
 pub mod moderation;
+pub mod music;
 pub mod register;
+pub mod settings;
+pub mod stats;
 pub mod sticky;
 pub mod utility;
 pub mod yuri;
@@ -1,14 +1,33 @@
 use crate::{Context, Error};
-use backend::music;
-use database::subscriptions::{ChannelType, SubscriptionMode, channel_subscription_handler};
+use backend::{
+    links::{self as link_rules, tracking::TRACKING_PROVIDER},
+    music,
+};
+use database::{
+    links::{
+        delete_rule, fetch_rules_for_guild, insert_rule, reset_provider_settings,
+        set_provider_enabled,
+    },
+    models::{LinkFixMode, NewLinkRule},
+    subscriptions::{ChannelType, SubscriptionMode, channel_subscription_handler},
+};
 use paste::paste;
-use poise::{reply::CreateReply, serenity_prelude::Mentionable};
-use tracing::info;
+use poise::{
+    reply::CreateReply,
+    serenity_prelude::{Colour, CreateEmbed, GuildChannel, Mentionable},
+};
+use tracing::{error, info};
+
+/// Discord's message limit, the rule listing stays under it even though it is sent as an embed
+const LISTING_LIMIT: usize = 2000;
+/// Ends a listing that had to be cut short
+const TRUNCATED: &str = "...";
+const ALL_PROVIDERS: &str = "all";
 
 #[poise::command(
     slash_command,
     rename = "mod",
-    subcommands("mapfeed", "music", "group")
+    subcommands("mapfeed", "music", "group", "links")
 )]
 pub async fn _mod(_: Context<'_>) -> Result<(), Error> {
     Ok(())
@@ -29,6 +48,24 @@ pub async fn group(_: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+#[poise::command(
+    slash_command,
+    subcommands(
+        "links_add",
+        "links_remove",
+        "links_list",
+        "links_test",
+        "links_toggle",
+        "links_providers",
+        "links_reset",
+        "links_mode",
+        "links_video"
+    )
+)]
+pub async fn links(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
 macro_rules! construct_commands {
     ($ident:ident, $middleware:expr, $help_text:literal) => {
         paste! {
@@ -88,3 +125,405 @@ construct_commands!(
     "music downloader"
 );
 construct_commands!(Groups, {}, "group tracker");
+
+/// Adds a link rewrite rule for this server
+#[poise::command(
+    slash_command,
+    rename = "add",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn links_add(
+    ctx: Context<'_>,
+    #[description = "The regex to match links with"] pattern: String,
+    #[description = "What to replace matches with, capture groups can be used with $1, $2, ..."]
+    replacement: String,
+    #[description = "A link this rule has to rewrite"] example: String,
+    #[description = "What the example link has to be rewritten to"] expected: String,
+    #[description = "Which provider this rule belongs to, used by /mod links toggle"]
+    provider: Option<String>,
+    #[description = "A replacement to use when the first mirror is down"] fallback: Option<String>,
+    #[description = "A replacement to use when the fallback is down as well"]
+    second_fallback: Option<String>,
+    #[description = "A link this rule must not match"] counterexample: Option<String>,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let examples = [link_rules::RuleExample {
+        input: example,
+        output: expected,
+    }];
+    let fallbacks = fallback
+        .into_iter()
+        .chain(second_fallback)
+        .map(|f| f.trim().to_string())
+        .collect::<Vec<_>>();
+    let counterexamples = counterexample.into_iter().collect::<Vec<_>>();
+    if let Err(e) = link_rules::validate_rule(
+        &pattern,
+        &replacement,
+        &fallbacks,
+        &examples,
+        &counterexamples,
+    ) {
+        let builder = CreateReply::default()
+            .content(e.to_string())
+            .ephemeral(true);
+        ctx.send(builder).await?;
+        return Ok(());
+    }
+
+    let rule = insert_rule(NewLinkRule {
+        guild_id: Some(guild_id.get() as i64),
+        pattern,
+        replacement,
+        builtin: false,
+        provider: provider
+            .map(|p| p.trim().to_lowercase())
+            .unwrap_or_else(|| "custom".to_string()),
+        fallbacks,
+    })
+    .await?;
+    link_rules::RULE_CACHE.reload().await?;
+    info!("Added link rule {} for guild {}", rule.id, guild_id);
+
+    let builder = CreateReply::default()
+        .content(format!("Added link rule `#{}`", rule.id))
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Removes a link rewrite rule from this server
+#[poise::command(
+    slash_command,
+    rename = "remove",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_remove(
+    ctx: Context<'_>,
+    #[description = "The id of the rule, shown in /mod links list"] id: i32,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let content = if delete_rule(id, guild_id.get() as i64).await? {
+        link_rules::RULE_CACHE.reload().await?;
+        info!("Removed link rule {} from guild {}", id, guild_id);
+        format!("Removed link rule `#{}`", id)
+    } else {
+        format!("This server doesn't have a link rule `#{}`", id)
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+/// Lists every link rewrite rule that applies in this server
+#[poise::command(
+    slash_command,
+    rename = "list",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_list(ctx: Context<'_>) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let mut description = String::new();
+    for rule in fetch_rules_for_guild(guild_id.get() as i64).await? {
+        let line = format!(
+            "`#{}` {} `{}`\n```{}```→ `{}`\n",
+            rule.id,
+            if rule.guild_id.is_some() {
+                "**Server**"
+            } else {
+                "**Global**"
+            },
+            escape_code(&rule.provider),
+            escape_code(&rule.pattern),
+            escape_code(&rule.replacement)
+        );
+        // Byte lengths, so the description never has more characters than Discord allows
+        if description.len() + line.len() + TRUNCATED.len() > LISTING_LIMIT {
+            description.push_str(TRUNCATED);
+            break;
+        }
+        description.push_str(&line);
+    }
+
+    let builder = CreateReply::default().ephemeral(true).embed(
+        CreateEmbed::default()
+            .title("Link rules")
+            .description(description)
+            .colour(Colour::new(0xfc4fca)),
+    );
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Swaps backticks for a look-alike so user text can't close the code span it is shown in
+fn escape_code(text: &str) -> String {
+    text.replace('`', "\u{2CB}")
+}
+
+/// Shows what the link fixer would turn a message into in this server
+#[poise::command(
+    slash_command,
+    rename = "test",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_test(
+    ctx: Context<'_>,
+    #[description = "The message content to test"] content: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().map(|id| id.get() as i64);
+    let channel_id = ctx.channel_id().get() as i64;
+
+    let reply = match link_rules::fix_content(guild_id, channel_id, &content).await {
+        Ok(Some(fixed)) => fixed,
+        Ok(None) => "No rules matched".to_string(),
+        Err(e) => {
+            error!("Something went wrong while testing link rules: {}", e);
+            format!("Something went wrong: `{}`", e)
+        }
+    };
+
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+async fn autocomplete_provider<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let guild_id = ctx.guild_id().map(|id| id.get() as i64);
+    let mut providers = vec![ALL_PROVIDERS.to_string()];
+    providers.extend(link_rules::RULE_CACHE.providers_for(guild_id).await);
+
+    providers
+        .into_iter()
+        .filter(move |p| p.starts_with(&partial.to_lowercase()))
+}
+
+/// Turns link fixing for a provider on or off in this server or a single channel
+#[poise::command(
+    slash_command,
+    rename = "toggle",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_toggle(
+    ctx: Context<'_>,
+    #[description = "The provider to toggle, or \"all\" for every provider"]
+    #[autocomplete = "autocomplete_provider"]
+    provider: String,
+    #[description = "Whether links from this provider should be fixed"] enabled: bool,
+    #[description = "Only apply to this channel, leave empty for the whole server"] channel: Option<
+        GuildChannel,
+    >,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let provider = provider.trim().to_lowercase();
+    let stored_provider = (provider != ALL_PROVIDERS).then(|| provider.clone());
+    set_provider_enabled(
+        guild_id.get() as i64,
+        channel.as_ref().map(|c| c.id.get() as i64),
+        stored_provider,
+        enabled,
+    )
+    .await?;
+    link_rules::PROVIDER_TOGGLES.reload().await?;
+    info!(
+        "Set provider {} to {} in guild {}",
+        provider, enabled, guild_id
+    );
+
+    let scope = match &channel {
+        Some(channel) => channel.mention().to_string(),
+        None => "this server".to_string(),
+    };
+    let builder = CreateReply::default()
+        .content(format!(
+            "{} link fixing for `{}` in {}",
+            if enabled { "Enabled" } else { "Disabled" },
+            provider,
+            scope
+        ))
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Shows which providers are fixed in a channel
+#[poise::command(
+    slash_command,
+    rename = "providers",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_providers(
+    ctx: Context<'_>,
+    #[description = "The channel to check, defaults to this one"] channel: Option<GuildChannel>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().map(|id| id.get() as i64);
+    let channel_id = channel.map(|c| c.id).unwrap_or(ctx.channel_id());
+
+    let mut description = String::new();
+    for provider in link_rules::RULE_CACHE.providers_for(guild_id).await {
+        let toggles = &link_rules::PROVIDER_TOGGLES;
+        let channel_id = channel_id.get() as i64;
+        let enabled = if provider == TRACKING_PROVIDER {
+            toggles.is_opted_in(guild_id, channel_id, &provider).await
+        } else {
+            toggles.is_enabled(guild_id, channel_id, &provider).await
+        };
+        description.push_str(&format!(
+            "{} `{}`\n",
+            if enabled { "✅" } else { "❌" },
+            provider
+        ));
+    }
+
+    let builder = CreateReply::default().ephemeral(true).embed(
+        CreateEmbed::default()
+            .title("Link providers")
+            .description(format!("In {}\n\n{}", channel_id.mention(), description))
+            .colour(Colour::new(0xfc4fca)),
+    );
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Removes every provider toggle in this server, turning providers back on and tracking off
+#[poise::command(
+    slash_command,
+    rename = "reset",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_reset(ctx: Context<'_>) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    reset_provider_settings(guild_id.get() as i64).await?;
+    link_rules::PROVIDER_TOGGLES.reload().await?;
+    info!("Reset provider toggles in guild {}", guild_id);
+
+    let builder = CreateReply::default()
+        .content("Reset every provider toggle in this server")
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+#[derive(poise::ChoiceParameter)]
+pub enum FixModeChoice {
+    #[name = "Reply as the bot"]
+    Reply,
+    #[name = "Repost as the author through a webhook"]
+    Webhook,
+    #[name = "Keep the original and reply with the fixed links"]
+    Suppress,
+}
+
+impl From<FixModeChoice> for LinkFixMode {
+    fn from(choice: FixModeChoice) -> Self {
+        match choice {
+            FixModeChoice::Reply => LinkFixMode::Reply,
+            FixModeChoice::Webhook => LinkFixMode::Webhook,
+            FixModeChoice::Suppress => LinkFixMode::Suppress,
+        }
+    }
+}
+
+/// Changes how fixed links are posted in this server
+#[poise::command(
+    slash_command,
+    rename = "mode",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_mode(
+    ctx: Context<'_>,
+    #[description = "How fixed links should be posted"] mode: FixModeChoice,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let mode = LinkFixMode::from(mode);
+    link_rules::GUILD_SETTINGS
+        .set_mode(guild_id.get() as i64, mode)
+        .await?;
+    info!("Set link fix mode to {} in guild {}", mode, guild_id);
+
+    let builder = CreateReply::default()
+        .content(format!("Fixed links will now be posted in `{}` mode", mode))
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Attaches the videos behind fixed Twitter, TikTok and Instagram links in a channel
+#[poise::command(
+    slash_command,
+    rename = "video",
+    category = "Mod",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn links_video(
+    ctx: Context<'_>,
+    #[description = "Whether videos should be downloaded and attached"] enabled: bool,
+    #[description = "The channel to change, defaults to this one"] channel: Option<GuildChannel>,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    let channel_id = channel.map(|c| c.id).unwrap_or(ctx.channel_id());
+
+    link_rules::video::VIDEO_CHANNELS
+        .set(channel_id.get() as i64, guild_id.get() as i64, enabled)
+        .await?;
+
+    let builder = CreateReply::default()
+        .content(format!(
+            "{} video downloads for fixed links in {}",
+            if enabled { "Enabled" } else { "Disabled" },
+            channel_id.mention()
+        ))
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
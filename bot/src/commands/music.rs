@@ -0,0 +1,208 @@
+use crate::{
+    Context, Error,
+    events::{download_error_reply, failure_summary, song_parts},
+};
+use backend::music::{
+    self, DownloadError, PendingSong,
+    clip::{Clip, parse_timestamp},
+    format::AudioFormat,
+    sources::{Found, SOURCES},
+};
+use common::limits::guild_upload_limit;
+use poise::{CreateReply, serenity_prelude as serenity};
+use tracing::info;
+
+#[derive(poise::ChoiceParameter)]
+pub enum FormatChoice {
+    #[name = "ogg"]
+    Ogg,
+    #[name = "mp3"]
+    Mp3,
+    #[name = "m4a"]
+    M4a,
+    #[name = "opus"]
+    Opus,
+}
+
+impl From<FormatChoice> for AudioFormat {
+    fn from(choice: FormatChoice) -> Self {
+        match choice {
+            FormatChoice::Ogg => AudioFormat::Ogg,
+            FormatChoice::Mp3 => AudioFormat::Mp3,
+            FormatChoice::M4a => AudioFormat::M4a,
+            FormatChoice::Opus => AudioFormat::Opus,
+        }
+    }
+}
+
+#[poise::command(slash_command, subcommands("download", "clip"))]
+pub async fn music(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Download the audio of a song, as an ogg file unless another format is picked
+#[poise::command(slash_command, category = "Music")]
+pub async fn download(
+    ctx: Context<'_>,
+    #[description = "A link to the song"] url: String,
+    #[description = "The file format to send"] format: Option<FormatChoice>,
+) -> Result<(), Error> {
+    let format = format.map(AudioFormat::from).unwrap_or_default();
+    request_and_send(ctx, &url, format).await
+}
+
+/// Downloads the audio of every music link in a message
+#[poise::command(context_menu_command = "Download audio")]
+pub async fn download_audio(ctx: Context<'_>, message: serenity::Message) -> Result<(), Error> {
+    request_and_send(ctx, &message.content, AudioFormat::default()).await
+}
+
+/// Download part of a song, timestamps look like 90, 1:30 or 1m30s
+#[poise::command(slash_command, category = "Music")]
+pub async fn clip(
+    ctx: Context<'_>,
+    #[description = "A link to the song"] url: String,
+    #[description = "Where the clip starts"] start: String,
+    #[description = "Where the clip ends, the end of the song if left out"] end: Option<String>,
+) -> Result<(), Error> {
+    let reply = |content: String| CreateReply::default().content(content).ephemeral(true);
+    let end = end
+        .as_deref()
+        .map(|end| parse_timestamp(end).ok_or(()))
+        .transpose();
+    let (Some(start), Ok(end)) = (parse_timestamp(&start), end) else {
+        ctx.send(reply(
+            "Timestamps have to look like 90, 1:30 or 1m30s".to_string(),
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    let mut track = match SOURCES.find(&url) {
+        Some(Found::Track(track)) => track,
+        Some(Found::Playlist(provider)) => {
+            let e = DownloadError::Playlist(provider);
+            ctx.send(reply(e.to_string())).await?;
+            return Ok(());
+        }
+        None => {
+            ctx.send(reply("That is not a supported music link".to_string()))
+                .await?;
+            return Ok(());
+        }
+    };
+    track.clip = match Clip::new(start, end) {
+        Ok(clip) => Some(clip),
+        Err(e) => {
+            ctx.send(reply(e.to_string())).await?;
+            return Ok(());
+        }
+    };
+
+    ctx.defer().await?;
+    info!("User {} is clipping {}", ctx.author().tag(), track.url);
+    let limit = guild_upload_limit(ctx.guild_id()).await;
+    let pending = match music::request_song(
+        track,
+        limit,
+        ctx.author().id.get(),
+        ctx.channel_id().get(),
+    )
+    .await
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            ctx.say(download_error_reply(&e)).await?;
+            return Ok(());
+        }
+    };
+
+    send_songs(ctx, vec![pending]).await
+}
+
+async fn request_and_send(
+    ctx: Context<'_>,
+    content: &str,
+    format: AudioFormat,
+) -> Result<(), Error> {
+    let found = SOURCES.find_all(content);
+    if found.is_empty() {
+        let builder = CreateReply::default()
+            .content("There are no supported music links in there")
+            .ephemeral(true);
+        ctx.send(builder).await?;
+        return Ok(());
+    }
+
+    ctx.defer().await?;
+    info!("User {} is downloading audio", ctx.author().tag());
+    let pending = match music::request_songs(
+        found,
+        format,
+        ctx.guild_id(),
+        ctx.author().id.get(),
+        ctx.channel_id().get(),
+    )
+    .await
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            ctx.say(download_error_reply(&e)).await?;
+            return Ok(());
+        }
+    };
+
+    send_songs(ctx, pending).await
+}
+
+/// Replies with `pending` once it is downloaded, saying where it is in the queue until then
+async fn send_songs(ctx: Context<'_>, pending: Vec<PendingSong>) -> Result<(), Error> {
+    let position = pending
+        .iter()
+        .map(PendingSong::position)
+        .max()
+        .unwrap_or_default();
+    let placeholder = match position {
+        0 => None,
+        position => Some(ctx.say(format!("Queued (#{})", position)).await?),
+    };
+
+    let total = pending.len();
+    let mut songs = Vec::new();
+    let mut failures = Vec::new();
+    for result in music::finish_all(pending).await {
+        match result {
+            Ok(song) => songs.push(song),
+            Err(e) => failures.push(download_error_reply(&e)),
+        }
+    }
+    let content = failure_summary(failures, total);
+
+    let limit = guild_upload_limit(ctx.guild_id()).await;
+    let mut batches = music::batch_songs(songs, limit).into_iter();
+    // The first batch replaces the placeholder, so it also carries any failures
+    let (embeds, attachments) = song_parts(batches.next().unwrap_or_default());
+    let builder = CreateReply {
+        content: (!content.is_empty()).then_some(content),
+        embeds,
+        attachments,
+        ..Default::default()
+    };
+    match placeholder {
+        Some(placeholder) => placeholder.edit(ctx, builder).await?,
+        None => {
+            ctx.send(builder).await?;
+        }
+    }
+    for batch in batches {
+        let (embeds, attachments) = song_parts(batch);
+        let builder = CreateReply {
+            embeds,
+            attachments,
+            ..Default::default()
+        };
+        ctx.send(builder).await?;
+    }
+
+    Ok(())
+}
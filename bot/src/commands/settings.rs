@@ -0,0 +1,35 @@
+use crate::{Context, Error};
+use database::settings::fetch_user_settings;
+use poise::{
+    CreateReply,
+    serenity_prelude::{Colour, CreateEmbed},
+};
+
+/// View your personal settings
+#[poise::command(slash_command, category = "Utility")]
+pub async fn settings(ctx: Context<'_>) -> Result<(), Error> {
+    let settings = fetch_user_settings(ctx.author().id.get() as i64).await?;
+
+    let fields = vec![(
+        "Link fixing",
+        format!(
+            "{}\nToggle with `/links optout` and `/links optin`",
+            if settings.links_opt_out {
+                "❌ Opted out"
+            } else {
+                "✅ Enabled"
+            }
+        ),
+        false,
+    )];
+
+    let builder = CreateReply::default().ephemeral(true).embed(
+        CreateEmbed::default()
+            .title("Your settings")
+            .colour(Colour::new(0xfc4fca))
+            .fields(fields),
+    );
+    ctx.send(builder).await?;
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::{Context, Error};
+use backend::links::stats::{self, LinkStatsSummary};
+use poise::{
+    ChoiceParameter, CreateReply,
+    serenity_prelude::{Colour, CreateAttachment, CreateEmbed, Mentionable, UserId},
+};
+use tracing::info;
+
+const TOP_ENTRIES: usize = 5;
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum StatsPeriod {
+    #[name = "Today"]
+    Day,
+    #[name = "Last 7 days"]
+    Week,
+    #[name = "Last 30 days"]
+    Month,
+    #[name = "Last year"]
+    Year,
+    #[name = "Everything stored"]
+    All,
+}
+
+impl StatsPeriod {
+    fn days(self) -> Option<u64> {
+        match self {
+            StatsPeriod::Day => Some(1),
+            StatsPeriod::Week => Some(7),
+            StatsPeriod::Month => Some(30),
+            StatsPeriod::Year => Some(365),
+            StatsPeriod::All => None,
+        }
+    }
+}
+
+#[poise::command(slash_command, subcommands("links", "export"))]
+pub async fn stats(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+fn ranking<T>(entries: &[(T, i64)], format: impl Fn(&T) -> String) -> String {
+    if entries.is_empty() {
+        return "Nothing yet".to_string();
+    }
+
+    entries
+        .iter()
+        .take(TOP_ENTRIES)
+        .enumerate()
+        .map(|(i, (key, count))| format!("{}. {} - {}\n", i + 1, format(key), count))
+        .collect()
+}
+
+fn summary_embed(summary: &LinkStatsSummary, period: StatsPeriod) -> CreateEmbed {
+    CreateEmbed::default()
+        .title(format!("Fixed links, {}", period.name().to_lowercase()))
+        .description(format!("**{}** links fixed", summary.total))
+        .field(
+            "Top providers",
+            ranking(&summary.providers, |provider| format!("`{}`", provider)),
+            true,
+        )
+        .field(
+            "Top posters",
+            ranking(&summary.posters, |user| {
+                UserId::new(*user).mention().to_string()
+            }),
+            true,
+        )
+        .colour(Colour::new(0xfc4fca))
+}
+
+/// Shows how many links were fixed in this server
+#[poise::command(slash_command, category = "Stats", guild_only)]
+pub async fn links(
+    ctx: Context<'_>,
+    #[description = "How far back to look, defaults to the last 30 days"] period: Option<
+        StatsPeriod,
+    >,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    let period = period.unwrap_or(StatsPeriod::Month);
+
+    let summary = stats::fetch_summary(guild_id.get(), stats::since(period.days())).await?;
+    ctx.send(CreateReply::default().embed(summary_embed(&summary, period)))
+        .await?;
+
+    Ok(())
+}
+
+/// Exports this servers link stats as a CSV file
+#[poise::command(
+    slash_command,
+    category = "Stats",
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "How far back to export, defaults to everything stored"] period: Option<
+        StatsPeriod,
+    >,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+    let period = period.unwrap_or(StatsPeriod::All);
+
+    let csv = stats::export_csv(guild_id.get(), stats::since(period.days())).await?;
+    info!("Exported link stats for guild {}", guild_id);
+
+    let builder = CreateReply::default()
+        .attachment(CreateAttachment::bytes(
+            csv.into_bytes(),
+            format!("link-stats-{}.csv", guild_id),
+        ))
+        .ephemeral(true);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
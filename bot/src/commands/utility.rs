@@ -1,4 +1,5 @@
 use crate::{Context, Data, Error};
+use backend::{links::mirrors::MIRROR_HEALTH, music::cache::MUSIC_CACHE};
 use common::sys::SYSTEM;
 use poise::{
     CreateReply,
@@ -82,6 +83,14 @@ pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
         ctx.data().uptime()
     );
 
+    let mirrors = MIRROR_HEALTH
+        .snapshot()
+        .into_iter()
+        .map(|(host, healthy)| format!("{} `{}`", if healthy { "✅" } else { "❌" }, host))
+        .collect::<Vec<_>>();
+
+    let cache = MUSIC_CACHE.stats().await;
+
     let fields = vec![
         (
             "Ping",
@@ -106,6 +115,26 @@ pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
             ),
             false,
         ),
+        (
+            "Music cache",
+            format!(
+                "Hits: `{}`\nMisses: `{}`\nSongs: `{}` (`{}`)",
+                cache.hits,
+                cache.misses,
+                cache.songs,
+                format_bytes(cache.bytes)
+            ),
+            false,
+        ),
+        (
+            "Link mirrors",
+            if mirrors.is_empty() {
+                "Not probed yet".to_string()
+            } else {
+                mirrors.join("\n")
+            },
+            false,
+        ),
     ];
 
     let builder = CreateReply::default().content("").embed(
@@ -1,12 +1,19 @@
 use crate::{Data, Error};
 use backend::{
     links,
-    music::{DownloadError, music_link_handler},
+    music::{self, DownloadError, PendingSong, Song, music_link_handler},
     sticky::sticky_message_handler,
 };
+use common::limits::guild_upload_limit;
+use database::{
+    links::{fetch_repost_for_original, forget_deleted_messages},
+    models::LinkFixMode,
+};
 use poise::serenity_prelude::{
-    self as serenity, CreateAttachment, CreateMessage, FullEvent, Message, MessageFlags,
+    self as serenity, CreateAttachment, CreateEmbed, CreateMessage, EditMessage, FullEvent,
+    Message, MessageFlags, MessageId, MessageUpdateEvent,
 };
+use std::collections::BTreeMap;
 use tracing::{error, info, warn};
 
 pub async fn listener(
@@ -20,6 +27,16 @@ pub async fn listener(
             info!("Logged in as {}", data_about_bot.user.name);
         }
         FullEvent::Message { new_message, .. } => handle_incoming_message(ctx, new_message).await?,
+        FullEvent::MessageUpdate { new, event, .. } => {
+            handle_message_edit(ctx, new.as_ref(), event).await?
+        }
+        FullEvent::MessageDelete {
+            deleted_message_id, ..
+        } => forget_deleted(vec![*deleted_message_id]).await,
+        FullEvent::MessageDeleteBulk {
+            multiple_deleted_messages_ids,
+            ..
+        } => forget_deleted(multiple_deleted_messages_ids.clone()).await,
         _ => {}
     }
 
@@ -30,67 +47,298 @@ async fn handle_incoming_message(
     ctx: &serenity::Context,
     new_message: &Message,
 ) -> Result<(), Error> {
-    match links::fix_links(new_message).await {
-        Ok(result) => {
-            if let Some(content) = result {
-                let mut target: &Message = new_message;
-                if let Some(reply_handle) = &new_message.referenced_message {
-                    target = reply_handle
-                }
-                match links::message_handler(
-                    content,
-                    new_message.author.id.get(),
-                    new_message.channel_id,
-                    target,
-                )
-                .await
-                {
-                    Ok(_) => (),
-                    Err(e) => {
-                        error!("Something went wrong while sending reply message: {}", e);
-                        return Ok(());
-                    }
-                }
-                new_message.delete(ctx).await?;
-                // TODO analytics
-                info!("Fixed up a message successfully")
-            }
-        }
+    fix_message_links(ctx, new_message).await?;
+
+    handle_music_link(ctx, new_message).await?;
+
+    match sticky_message_handler(new_message).await {
+        Ok(_) => {}
+        Err(e) => error!("Something went wrong while sending sticky message: {}", e),
+    }
+
+    Ok(())
+}
+
+async fn handle_music_link(ctx: &serenity::Context, new_message: &Message) -> Result<(), Error> {
+    let pending = match music_link_handler(new_message).await {
+        Ok(pending) if pending.is_empty() => return Ok(()),
+        Ok(pending) => pending,
         Err(e) => {
-            new_message.reply(ctx, "Something went wrong").await?;
-            error!("Something went wrong while fixing a link! {}", e)
+            new_message.reply(&ctx, download_error_reply(&e)).await?;
+            return Ok(());
         }
     };
 
-    match music_link_handler(new_message).await {
-        Ok(Some(song)) => {
+    let mut placeholder = None;
+    let position = pending
+        .iter()
+        .map(PendingSong::position)
+        .max()
+        .unwrap_or_default();
+    if position > 0 {
+        let builder = CreateMessage::new()
+            .content(format!("Queued (#{})", position))
+            .flags(MessageFlags::SUPPRESS_NOTIFICATIONS)
+            .reference_message(new_message);
+        placeholder = Some(new_message.channel_id.send_message(&ctx, builder).await?);
+    }
+
+    let total = pending.len();
+    let mut songs = Vec::new();
+    let mut failures = Vec::new();
+    for result in music::finish_all(pending).await {
+        match result {
+            Ok(song) => songs.push(song),
+            Err(e) => failures.push(download_error_reply(&e)),
+        }
+    }
+    let content = failure_summary(failures, total);
+
+    let limit = guild_upload_limit(new_message.guild_id).await;
+    let mut batches = music::batch_songs(songs, limit).into_iter();
+    // The first batch replaces the placeholder, so it also carries any failures
+    let (embeds, files) = song_parts(batches.next().unwrap_or_default());
+    match placeholder {
+        Some(mut placeholder) => {
+            let mut builder = EditMessage::new().content(content).embeds(embeds);
+            for file in files {
+                builder = builder.new_attachment(file);
+            }
+            placeholder.edit(&ctx, builder).await?;
+        }
+        None => {
             let builder = CreateMessage::new()
-                .add_file(CreateAttachment::bytes(song.get(), "audio.ogg"))
+                .content(content)
+                .embeds(embeds)
+                .add_files(files)
                 .flags(MessageFlags::SUPPRESS_NOTIFICATIONS)
                 .reference_message(new_message);
-
             new_message.channel_id.send_message(&ctx, builder).await?;
         }
-        Ok(None) => (),
-        Err(DownloadError::FileTooLarge) => {
+    }
+    for batch in batches {
+        let (embeds, files) = song_parts(batch);
+        let builder = CreateMessage::new()
+            .embeds(embeds)
+            .add_files(files)
+            .flags(MessageFlags::SUPPRESS_NOTIFICATIONS)
+            .reference_message(new_message);
+        new_message.channel_id.send_message(&ctx, builder).await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn song_parts(songs: Vec<Song>) -> (Vec<CreateEmbed>, Vec<CreateAttachment>) {
+    let mut embeds = Vec::new();
+    let mut files = Vec::new();
+    for song in songs {
+        embeds.extend(song.embed());
+        let filename = song.filename();
+        files.push(CreateAttachment::bytes(song.get(), filename));
+    }
+
+    (embeds, files)
+}
+
+pub(crate) fn failure_summary(failures: Vec<String>, total: usize) -> String {
+    match failures.as_slice() {
+        [] => String::new(),
+        [only] if total == 1 => only.clone(),
+        _ => {
+            let failed = failures.len();
+            // Songs skipped for the same reason share one line
+            let mut reasons: BTreeMap<String, usize> = BTreeMap::new();
+            for reason in failures {
+                *reasons.entry(reason).or_default() += 1;
+            }
+            format!(
+                "Failed to download {} of {} songs\n{}",
+                failed,
+                total,
+                reasons
+                    .iter()
+                    .map(|(reason, count)| match count {
+                        1 => format!("- {}", reason),
+                        count => format!("- {} ({} songs)", reason, count),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        }
+    }
+}
+
+pub(crate) fn download_error_reply(e: &DownloadError) -> String {
+    match e {
+        DownloadError::FileTooLarge => {
             warn!("File exceeds upload size");
-            new_message
-                .reply(&ctx, "File is too large to download")
-                .await?;
+            "File is too large to download".to_string()
         }
-        Err(DownloadError::DownloadTimeout(e)) => {
+        DownloadError::DownloadTimeout(e) => {
             warn!("Download took too long, {}", e);
-            new_message.reply(&ctx, "Download timed out").await?;
+            "Download timed out".to_string()
         }
-        Err(_) => {
-            new_message.reply(&ctx, "Failed to download audio").await?;
+        DownloadError::Playlist(_)
+        | DownloadError::InvalidClip(_)
+        | DownloadError::UserRateLimited(_)
+        | DownloadError::ChannelRateLimited(_)
+        | DownloadError::TooManySongs(_) => e.to_string(),
+        _ => "Failed to download audio".to_string(),
+    }
+}
+
+/// `cached` is the edited message from the cache, it is only fetched when the cache missed it
+async fn handle_message_edit(
+    ctx: &serenity::Context,
+    cached: Option<&Message>,
+    event: &MessageUpdateEvent,
+) -> Result<(), Error> {
+    // Updates without an edit timestamp are embeds loading or flags changing, not the author
+    if event.edited_timestamp.is_none() || event.author.as_ref().is_some_and(|a| a.bot) {
+        return Ok(());
+    }
+    let repost = fetch_repost_for_original(event.id.get() as i64).await?;
+    // Without a repost to update, only an edit that leaves links in the message needs a look
+    let has_links = event
+        .content
+        .as_deref()
+        .is_some_and(|content| !links::tracking::find_urls(content).is_empty());
+    if repost.is_none() && !has_links {
+        return Ok(());
+    }
+    let message = match cached {
+        Some(message) => message.clone(),
+        None => event.channel_id.message(ctx, event.id).await?,
+    };
+
+    match repost {
+        Some(repost) => {
+            let fixed = match links::fix_message(&message).await {
+                Ok(fixed) => fixed,
+                Err(e) => {
+                    error!("Something went wrong while fixing an edited link! {}", e);
+                    return Ok(());
+                }
+            };
+            if let Err(e) = links::repost::update_repost(repost, fixed).await {
+                error!("Something went wrong while updating a repost: {}", e);
+            }
+        }
+        // Links added by the edit get fixed like a new message
+        None => fix_message_links(ctx, &message).await?,
+    }
+
+    Ok(())
+}
+
+/// Stops tracking reposts that can no longer be edited
+async fn forget_deleted(message_ids: Vec<MessageId>) {
+    let ids = message_ids.iter().map(|id| id.get() as i64).collect();
+    if let Err(e) = forget_deleted_messages(ids).await {
+        error!("Failed to forget reposts of deleted messages: {}", e);
+    }
+}
+
+async fn fix_message_links(ctx: &serenity::Context, new_message: &Message) -> Result<(), Error> {
+    let guild_id = new_message.guild_id.map(|id| id.get() as i64);
+    let channel_id = new_message.channel_id.get() as i64;
+    let opted_out = links::OPT_OUT_CACHE
+        .check(new_message.author.id.get() as i64)
+        .await;
+    if opted_out
+        || !links::PROVIDER_TOGGLES
+            .any_enabled(guild_id, channel_id)
+            .await
+    {
+        return Ok(());
+    }
+
+    let mut fixed = match links::fix_message(new_message).await {
+        Ok(Some(fixed)) => fixed,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            new_message.reply(ctx, "Something went wrong").await?;
+            error!("Something went wrong while fixing a link! {}", e);
+            return Ok(());
         }
     };
 
-    match sticky_message_handler(new_message).await {
-        Ok(_) => {}
-        Err(e) => error!("Something went wrong while sending sticky message: {}", e),
+    let providers = fixed.providers.clone();
+    let mut mode = links::GUILD_SETTINGS.mode(guild_id).await;
+    if mode != LinkFixMode::Suppress && !links::repost::can_delete(new_message).await {
+        mode = LinkFixMode::Suppress;
+    }
+    let music_channel = music::CHANNEL_CACHE.check(channel_id).await;
+    let mode = links::repost::mode_for(mode, &new_message.content, music_channel);
+
+    let videos_enabled = links::video::VIDEO_CHANNELS
+        .is_enabled(new_message.channel_id.get() as i64)
+        .await;
+
+    if mode == LinkFixMode::Suppress {
+        let mut videos = Vec::new();
+        if videos_enabled {
+            videos = links::video::download_videos(&mut fixed, new_message, 0).await;
+        }
+        if let Err(e) = links::companion_handler(fixed.links, videos, new_message)
+            .await
+            .map_err(|e| e.to_string())
+        {
+            error!(
+                "Something went wrong while sending companion message: {}",
+                e
+            );
+            return Ok(());
+        }
+    } else {
+        let mut carryover = links::repost::collect_carryover(new_message).await;
+        if videos_enabled {
+            let used = carryover.attachments.iter().map(|a| a.data.len()).sum();
+            let videos = links::video::download_videos(&mut fixed, new_message, used).await;
+            carryover.attachments.extend(videos);
+        }
+        let content = fixed.content;
+        let mut target: &Message = new_message;
+        if let Some(reply_handle) = &new_message.referenced_message {
+            target = reply_handle
+        }
+        let mode = match mode {
+            LinkFixMode::Webhook if !carryover.fits_webhook(new_message) => LinkFixMode::Reply,
+            mode => mode,
+        };
+        let sent = match mode {
+            LinkFixMode::Webhook => {
+                match links::webhook::webhook_handler(content.clone(), new_message, &carryover)
+                    .await
+                {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        warn!("Webhook repost failed, falling back to a reply: {}", e);
+                        links::message_handler(content, new_message, target, &carryover)
+                            .await
+                            .map_err(|e| e.to_string())
+                    }
+                }
+            }
+            _ => links::message_handler(content, new_message, target, &carryover)
+                .await
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = sent {
+            error!("Something went wrong while sending reply message: {}", e);
+            return Ok(());
+        }
+        if carryover.complete {
+            new_message.delete(ctx).await?;
+        } else {
+            info!("Kept {} as it could not be fully reposted", new_message.id);
+        }
+    }
+    if let Err(e) = links::stats::record(new_message, &providers).await {
+        error!("Failed to record link stats: {}", e);
     }
+    info!("Fixed up a message successfully");
 
     Ok(())
 }
@@ -9,6 +9,9 @@ mod commands;
 mod events;
 mod tasks;
 
+/// Messages kept per channel, so edits can be handled without fetching the message again
+const CACHED_MESSAGES: usize = 50;
+
 pub struct Data {
     startup_time: Instant,
 }
@@ -58,6 +61,12 @@ async fn main() {
             commands::utility::status(),
             commands::cat::cat(),
             commands::sticky::sticky(),
+            commands::links::links(),
+            commands::links::delete_fixed(),
+            commands::settings::settings(),
+            commands::stats::stats(),
+            commands::music::music(),
+            commands::music::download_audio(),
         ],
 
         event_handler: |ctx, event, framework, data| {
@@ -85,7 +94,11 @@ async fn main() {
         })
         .build();
 
+    let mut cache_settings = serenity::cache::Settings::default();
+    cache_settings.max_messages = CACHED_MESSAGES;
+
     let mut client: serenity::Client = serenity::ClientBuilder::new(token, intents)
+        .cache_settings(cache_settings)
         .framework(framework)
         .await
         .unwrap_or_else(|err| {
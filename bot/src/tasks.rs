@@ -1,9 +1,14 @@
 use backend::{
     api::osu::AuthenticationManager,
     groups::GroupManager,
+    links::{
+        GUILD_SETTINGS, PROVIDER_TOGGLES, RULE_CACHE, mirrors::MirrorManager, seed_default_rules,
+        stats::StatsManager, video::VIDEO_CHANNELS,
+    },
     mapfeed::{MapfeedManager, populate},
+    music::cache::MUSIC_CACHE,
 };
-use log::{info, warn};
+use log::{error, info, warn};
 use once_cell::sync::OnceCell;
 use tokio::time::{Duration, sleep};
 
@@ -20,6 +25,27 @@ pub async fn init_tasks() {
             .expect("Failed to set background task status to initialised")
     }
 
+    if let Err(e) = seed_default_rules().await {
+        error!("Failed to seed default link rules, {}", e);
+    }
+    if let Err(e) = RULE_CACHE.reload().await {
+        error!("Failed to load link rules, using embedded defaults, {}", e);
+    }
+    if let Err(e) = PROVIDER_TOGGLES.reload().await {
+        error!("Failed to load link provider settings, {}", e);
+    }
+    if let Err(e) = GUILD_SETTINGS.reload().await {
+        error!("Failed to load guild link settings, {}", e);
+    }
+    if let Err(e) = VIDEO_CHANNELS.reload().await {
+        error!("Failed to load video channels, {}", e);
+    }
+    if let Err(e) = MUSIC_CACHE.reload().await {
+        error!("Failed to load the music cache, {}", e);
+    }
+    MirrorManager::start();
+    StatsManager::start();
+
     // TODO Ability to manage if the loop is running or not
     AuthenticationManager::new().await;
 
@@ -1,3 +1,4 @@
 pub mod context;
+pub mod limits;
 pub mod math;
 pub mod sys;
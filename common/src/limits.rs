@@ -0,0 +1,29 @@
+use crate::context::get_context_wrapper;
+use serenity::all::{GuildId, PremiumTier};
+
+const MEBIBYTE: usize = 1024 * 1024;
+
+/// The largest upload Discord accepts for a guild at its boost tier
+pub fn upload_limit(tier: PremiumTier) -> usize {
+    match tier {
+        PremiumTier::Tier2 => 50 * MEBIBYTE,
+        PremiumTier::Tier3 => 100 * MEBIBYTE,
+        _ => 10 * MEBIBYTE,
+    }
+}
+
+/// Looks up the upload limit for a guild, DMs and unknown guilds get the base limit
+pub async fn guild_upload_limit(guild_id: Option<GuildId>) -> usize {
+    let Some(guild_id) = guild_id else {
+        return upload_limit(PremiumTier::Tier0);
+    };
+
+    let ctx = get_context_wrapper();
+    if let Some(guild) = ctx.cache.guild(guild_id) {
+        return upload_limit(guild.premium_tier);
+    }
+    match guild_id.to_partial_guild(&ctx.http).await {
+        Ok(guild) => upload_limit(guild.premium_tier),
+        Err(_) => upload_limit(PremiumTier::Tier0),
+    }
+}
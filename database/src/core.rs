@@ -70,7 +70,7 @@ impl Database {
         }
     }
 
-    pub async fn get_conn(&self) -> DbPooledConnection {
+    pub async fn get_conn(&self) -> DbPooledConnection<'_> {
         for i in 0..3 {
             match self.pool.get().await {
                 Ok(p) => return p,
@@ -83,7 +83,7 @@ impl Database {
         panic!("Cannot get connection from pool.");
     }
 
-    pub async fn get_conn_unchecked(&self) -> DbPooledConnection {
+    pub async fn get_conn_unchecked(&self) -> DbPooledConnection<'_> {
         self.pool
             .get()
             .await
@@ -110,3 +110,22 @@ async fn run_migrations(
 
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::sync::OnceCell;
+
+    static DB_INIT: OnceCell<()> = OnceCell::const_new();
+
+    /// Shared between every test module as the pool can only be initialised once per process
+    pub(crate) async fn init_db() {
+        DB_INIT
+            .get_or_init(|| async {
+                std::env::set_var("DATABASE_URL", "postgres://postgres@127.0.0.1:5432/testing");
+                super::initialise()
+                    .await
+                    .expect("Failed to initialise database");
+            })
+            .await;
+    }
+}
@@ -246,24 +246,11 @@ pub async fn update_osu_user_profile(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::tests::init_db;
     use diesel_async::RunQueryDsl;
     use pretty_assertions::assert_eq;
     use serde_json::json;
     use smallvec::smallvec;
-    use tokio::sync::OnceCell;
-
-    static DB_INIT: OnceCell<()> = OnceCell::const_new();
-
-    async fn init_db() {
-        DB_INIT
-            .get_or_init(|| async {
-                std::env::set_var("DATABASE_URL", "postgres://postgres@127.0.0.1:5432/testing");
-                crate::core::initialise()
-                    .await
-                    .expect("Failed to initialise database");
-            })
-            .await;
-    }
 
     #[tokio::test]
     async fn fetch_per_group() {
@@ -571,7 +558,7 @@ mod tests {
             .await
             .unwrap()
             .into_iter()
-            .nth(0)
+            .next()
             .unwrap();
         let expected = OsuUser {
             id: 1000,
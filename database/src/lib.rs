@@ -1,7 +1,10 @@
 pub mod core;
 pub mod groups;
+pub mod links;
 pub mod mapfeed;
 pub mod models;
 mod schema;
+pub mod settings;
+pub mod stats;
 pub mod sticky;
 pub mod subscriptions;
@@ -0,0 +1,555 @@
+use crate::{
+    core::{DB, macros::get_conn},
+    models::{
+        LinkFixMode, LinkGuildSettings, LinkProviderSettings, LinkReposts, LinkRules,
+        LinkVideoChannels, LinkWebhooks, NewLinkRule,
+    },
+    schema::{
+        self, link_guild_settings::dsl::link_guild_settings,
+        link_provider_settings::dsl::link_provider_settings, link_reposts::dsl::link_reposts,
+        link_rules::dsl::link_rules, link_video_channels::dsl::link_video_channels,
+        link_webhooks::dsl::link_webhooks,
+    },
+};
+use anyhow::Result;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper,
+    upsert::excluded,
+};
+use diesel_async::{AsyncConnection, RunQueryDsl, scoped_futures::ScopedFutureExt};
+use tracing::{debug, info, instrument};
+
+/// Fetches every stored rule, global and guild scoped
+pub async fn fetch_all_rules() -> Result<Vec<LinkRules>> {
+    let rules = link_rules
+        .order(schema::link_rules::id.asc())
+        .select(LinkRules::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(rules)
+}
+
+/// Fetches the rules that apply inside a guild, this includes global rules
+pub async fn fetch_rules_for_guild(guild_id: i64) -> Result<Vec<LinkRules>> {
+    let rules = link_rules
+        .filter(schema::link_rules::guild_id.eq(guild_id))
+        .or_filter(schema::link_rules::guild_id.is_null())
+        .order(schema::link_rules::id.asc())
+        .select(LinkRules::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(rules)
+}
+
+#[instrument]
+pub async fn insert_rule(rule: NewLinkRule) -> Result<LinkRules> {
+    let rule = diesel::insert_into(link_rules)
+        .values(rule)
+        .returning(LinkRules::as_returning())
+        .get_result(get_conn!())
+        .await?;
+    debug!("Inserted");
+
+    Ok(rule)
+}
+
+/// Deletes a guild scoped rule, returns `false` if the guild does not own a rule with that id
+#[instrument]
+pub async fn delete_rule(id: i32, guild_id: i64) -> Result<bool> {
+    let deleted = diesel::delete(link_rules)
+        .filter(schema::link_rules::id.eq(id))
+        .filter(schema::link_rules::guild_id.eq(guild_id))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted {} rows", deleted);
+
+    Ok(deleted > 0)
+}
+
+/// Makes the builtin rules match `rules`, keyed by their pattern
+///
+/// Builtin rules are seeded from `patterns.json` on startup so editing the file
+/// and redeploying keeps the database in sync, user added rules are left alone.
+/// Rules that stay keep their id, so `/mod links` ids survive restarts
+pub async fn sync_builtin_rules(rules: Vec<NewLinkRule>) -> Result<()> {
+    let count = rules.len();
+    get_conn!()
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let existing: Vec<(i32, String)> = link_rules
+                    .filter(schema::link_rules::builtin.eq(true))
+                    .select((schema::link_rules::id, schema::link_rules::pattern))
+                    .load(conn)
+                    .await?;
+
+                let mut kept = Vec::new();
+                for rule in rules {
+                    match existing
+                        .iter()
+                        .find(|(_, pattern)| *pattern == rule.pattern)
+                    {
+                        Some((id, _)) => {
+                            diesel::update(link_rules.find(*id))
+                                .set((
+                                    schema::link_rules::replacement.eq(rule.replacement),
+                                    schema::link_rules::provider.eq(rule.provider),
+                                    schema::link_rules::fallbacks.eq(rule.fallbacks),
+                                ))
+                                .execute(conn)
+                                .await?;
+                            kept.push(*id);
+                        }
+                        None => {
+                            let id = diesel::insert_into(link_rules)
+                                .values(rule)
+                                .returning(schema::link_rules::id)
+                                .get_result(conn)
+                                .await?;
+                            kept.push(id);
+                        }
+                    }
+                }
+
+                diesel::delete(link_rules)
+                    .filter(schema::link_rules::builtin.eq(true))
+                    .filter(schema::link_rules::id.ne_all(kept))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+    info!("Synced {} builtin link rules", count);
+
+    Ok(())
+}
+
+pub async fn fetch_all_provider_settings() -> Result<Vec<LinkProviderSettings>> {
+    let settings = link_provider_settings
+        .select(LinkProviderSettings::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(settings)
+}
+
+/// Turns a provider on or off for a guild or a single channel
+///
+/// Passing `None` as the provider toggles every provider at once
+#[instrument]
+pub async fn set_provider_enabled(
+    guild_id: i64,
+    channel_id: Option<i64>,
+    provider: Option<String>,
+    enabled: bool,
+) -> Result<()> {
+    diesel::insert_into(link_provider_settings)
+        .values((
+            schema::link_provider_settings::guild_id.eq(guild_id),
+            schema::link_provider_settings::channel_id.eq(channel_id),
+            schema::link_provider_settings::provider.eq(provider),
+            schema::link_provider_settings::enabled.eq(enabled),
+        ))
+        .on_conflict((
+            schema::link_provider_settings::guild_id,
+            schema::link_provider_settings::channel_id,
+            schema::link_provider_settings::provider,
+        ))
+        .do_update()
+        .set(
+            schema::link_provider_settings::enabled
+                .eq(excluded(schema::link_provider_settings::enabled)),
+        )
+        .execute(get_conn!())
+        .await?;
+    debug!("Upserted");
+
+    Ok(())
+}
+
+/// Removes every provider override for a guild
+#[instrument]
+pub async fn reset_provider_settings(guild_id: i64) -> Result<()> {
+    diesel::delete(link_provider_settings)
+        .filter(schema::link_provider_settings::guild_id.eq(guild_id))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted");
+
+    Ok(())
+}
+
+pub async fn fetch_all_guild_settings() -> Result<Vec<LinkGuildSettings>> {
+    let settings = link_guild_settings
+        .select(LinkGuildSettings::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(settings)
+}
+
+#[instrument]
+pub async fn set_fix_mode(guild_id: i64, mode: LinkFixMode) -> Result<()> {
+    diesel::insert_into(link_guild_settings)
+        .values((
+            schema::link_guild_settings::guild_id.eq(guild_id),
+            schema::link_guild_settings::mode.eq(mode),
+        ))
+        .on_conflict(schema::link_guild_settings::guild_id)
+        .do_update()
+        .set(schema::link_guild_settings::mode.eq(excluded(schema::link_guild_settings::mode)))
+        .execute(get_conn!())
+        .await?;
+    debug!("Upserted");
+
+    Ok(())
+}
+
+pub async fn fetch_webhook(channel_id: i64) -> Result<Option<LinkWebhooks>> {
+    let webhook = link_webhooks
+        .filter(schema::link_webhooks::channel_id.eq(channel_id))
+        .select(LinkWebhooks::as_select())
+        .first(get_conn!())
+        .await
+        .optional()?;
+
+    Ok(webhook)
+}
+
+#[instrument(skip(webhook))]
+pub async fn store_webhook(webhook: LinkWebhooks) -> Result<()> {
+    diesel::insert_into(link_webhooks)
+        .values(&webhook)
+        .on_conflict(schema::link_webhooks::channel_id)
+        .do_update()
+        .set(schema::link_webhooks::webhook_url.eq(&webhook.webhook_url))
+        .execute(get_conn!())
+        .await?;
+    debug!("Upserted");
+
+    Ok(())
+}
+
+#[instrument]
+pub async fn delete_webhook(channel_id: i64) -> Result<()> {
+    diesel::delete(link_webhooks)
+        .filter(schema::link_webhooks::channel_id.eq(channel_id))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted");
+
+    Ok(())
+}
+
+#[instrument]
+pub async fn track_repost(repost: LinkReposts) -> Result<()> {
+    diesel::insert_into(link_reposts)
+        .values(repost)
+        .execute(get_conn!())
+        .await?;
+    debug!("Inserted");
+
+    Ok(())
+}
+
+pub async fn fetch_repost(bot_message_id: i64) -> Result<Option<LinkReposts>> {
+    let repost = link_reposts
+        .filter(schema::link_reposts::bot_message_id.eq(bot_message_id))
+        .select(LinkReposts::as_select())
+        .first(get_conn!())
+        .await
+        .optional()?;
+
+    Ok(repost)
+}
+
+/// Finds the repost made for an original message, if it is still tracked
+pub async fn fetch_repost_for_original(original_message_id: i64) -> Result<Option<LinkReposts>> {
+    let repost = link_reposts
+        .filter(schema::link_reposts::original_message_id.eq(original_message_id))
+        .select(LinkReposts::as_select())
+        .first(get_conn!())
+        .await
+        .optional()?;
+
+    Ok(repost)
+}
+
+#[instrument]
+pub async fn untrack_repost(bot_message_id: i64) -> Result<()> {
+    diesel::delete(link_reposts)
+        .filter(schema::link_reposts::bot_message_id.eq(bot_message_id))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted");
+
+    Ok(())
+}
+
+/// Forgets reposts tied to messages that were deleted
+///
+/// Deleted reposts are dropped, as are reposts of deleted originals, except webhook reposts,
+/// which stay tracked without their original so their owner can still delete them
+#[instrument]
+pub async fn forget_deleted_messages(message_ids: Vec<i64>) -> Result<()> {
+    diesel::delete(link_reposts)
+        .filter(schema::link_reposts::bot_message_id.eq_any(&message_ids))
+        .or_filter(
+            schema::link_reposts::original_message_id
+                .eq_any(&message_ids)
+                .and(schema::link_reposts::mode.ne(LinkFixMode::Webhook)),
+        )
+        .execute(get_conn!())
+        .await?;
+    diesel::update(link_reposts)
+        .filter(schema::link_reposts::original_message_id.eq_any(&message_ids))
+        .set(schema::link_reposts::original_message_id.eq(None::<i64>))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted");
+
+    Ok(())
+}
+
+pub async fn fetch_all_video_channels() -> Result<Vec<LinkVideoChannels>> {
+    let channels = link_video_channels
+        .select(LinkVideoChannels::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(channels)
+}
+
+/// Turns video downloads on or off in a channel, channels without a row have them off
+#[instrument]
+pub async fn set_video_channel(channel_id: i64, guild_id: i64, enabled: bool) -> Result<()> {
+    if enabled {
+        diesel::insert_into(link_video_channels)
+            .values(LinkVideoChannels {
+                channel_id,
+                guild_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(get_conn!())
+            .await?;
+        debug!("Inserted");
+    } else {
+        diesel::delete(link_video_channels)
+            .filter(schema::link_video_channels::channel_id.eq(channel_id))
+            .execute(get_conn!())
+            .await?;
+        debug!("Deleted");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::init_db;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn guild_scoped_rules() {
+        init_db().await;
+
+        let rule = insert_rule(NewLinkRule {
+            guild_id: Some(100),
+            pattern: "foo".to_string(),
+            replacement: "bar".to_string(),
+            builtin: false,
+            provider: "custom".to_string(),
+            fallbacks: vec![],
+        })
+        .await
+        .unwrap();
+
+        let visible = fetch_rules_for_guild(100).await.unwrap();
+        let hidden = fetch_rules_for_guild(101).await.unwrap();
+        assert!(visible.iter().any(|r| r.id == rule.id));
+        assert!(!hidden.iter().any(|r| r.id == rule.id));
+
+        assert!(!delete_rule(rule.id, 101).await.unwrap());
+        assert!(delete_rule(rule.id, 100).await.unwrap());
+        assert!(
+            !fetch_all_rules()
+                .await
+                .unwrap()
+                .iter()
+                .any(|r| r.id == rule.id)
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_builtins() {
+        init_db().await;
+
+        let builtin = |pattern: &str| NewLinkRule {
+            guild_id: None,
+            pattern: pattern.to_string(),
+            replacement: "replacement".to_string(),
+            builtin: true,
+            provider: "custom".to_string(),
+            fallbacks: vec![],
+        };
+
+        let builtins = || async {
+            fetch_all_rules()
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|r| r.builtin)
+                .map(|r| (r.pattern, (r.id, r.replacement)))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        sync_builtin_rules(vec![builtin("a"), builtin("b")])
+            .await
+            .unwrap();
+        let first = builtins().await;
+        sync_builtin_rules(vec![
+            NewLinkRule {
+                replacement: "changed".to_string(),
+                ..builtin("b")
+            },
+            builtin("c"),
+        ])
+        .await
+        .unwrap();
+        let second = builtins().await;
+
+        assert_eq!(
+            second.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["b", "c"]
+        );
+        // Rules that stay keep their id and pick up changes
+        assert_eq!(first["b"].0, second["b"].0);
+        assert_eq!(second["b"].1, "changed");
+    }
+
+    #[tokio::test]
+    async fn upsert_provider_settings() {
+        init_db().await;
+
+        set_provider_enabled(200, None, None, false).await.unwrap();
+        set_provider_enabled(200, Some(1), Some("tiktok".to_string()), false)
+            .await
+            .unwrap();
+        set_provider_enabled(200, None, None, true).await.unwrap();
+        set_provider_enabled(200, Some(1), Some("tiktok".to_string()), true)
+            .await
+            .unwrap();
+
+        let settings = fetch_all_provider_settings()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|s| s.guild_id == 200)
+            .collect::<Vec<_>>();
+        reset_provider_settings(200).await.unwrap();
+
+        assert_eq!(settings.len(), 2);
+        assert!(settings.iter().all(|s| s.enabled));
+    }
+
+    #[tokio::test]
+    async fn fix_mode_roundtrip() {
+        init_db().await;
+
+        for expected in [LinkFixMode::Webhook, LinkFixMode::Suppress] {
+            set_fix_mode(400, expected).await.unwrap();
+            let mode = fetch_all_guild_settings()
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|s| s.guild_id == 400)
+                .map(|s| s.mode);
+            assert_eq!(Some(expected), mode);
+        }
+        set_fix_mode(400, LinkFixMode::Reply).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn toggle_video_channels() {
+        init_db().await;
+
+        let enabled = || async {
+            fetch_all_video_channels()
+                .await
+                .unwrap()
+                .iter()
+                .any(|c| c.channel_id == 600 && c.guild_id == 601)
+        };
+        set_video_channel(600, 601, true).await.unwrap();
+        // Enabling twice is a no-op
+        set_video_channel(600, 601, true).await.unwrap();
+        assert!(enabled().await);
+
+        set_video_channel(600, 601, false).await.unwrap();
+        assert!(!enabled().await);
+    }
+
+    #[tokio::test]
+    async fn track_reposts() {
+        init_db().await;
+
+        track_repost(LinkReposts {
+            bot_message_id: 500,
+            channel_id: 1,
+            owner_id: 2,
+            original_message_id: Some(499),
+            mode: LinkFixMode::Suppress,
+        })
+        .await
+        .unwrap();
+        let tracked = fetch_repost(500).await.unwrap().map(|r| r.owner_id);
+        let by_original = fetch_repost_for_original(499)
+            .await
+            .unwrap()
+            .map(|r| (r.bot_message_id, r.mode));
+        untrack_repost(500).await.unwrap();
+
+        assert_eq!(Some(2), tracked);
+        assert_eq!(Some((500, LinkFixMode::Suppress)), by_original);
+        assert!(fetch_repost(500).await.unwrap().is_none());
+        assert!(fetch_repost_for_original(499).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn forget_deleted_reposts() {
+        init_db().await;
+
+        let repost = |bot_message_id, original_message_id, mode| LinkReposts {
+            bot_message_id,
+            channel_id: 1,
+            owner_id: 2,
+            original_message_id: Some(original_message_id),
+            mode,
+        };
+        for id in [510, 512, 514] {
+            untrack_repost(id).await.unwrap();
+        }
+        track_repost(repost(510, 509, LinkFixMode::Suppress))
+            .await
+            .unwrap();
+        track_repost(repost(512, 511, LinkFixMode::Reply))
+            .await
+            .unwrap();
+        track_repost(repost(514, 513, LinkFixMode::Webhook))
+            .await
+            .unwrap();
+
+        forget_deleted_messages(vec![510, 511, 513]).await.unwrap();
+
+        assert!(fetch_repost(510).await.unwrap().is_none());
+        assert!(fetch_repost(512).await.unwrap().is_none());
+        let webhook = fetch_repost(514).await.unwrap().unwrap();
+        assert_eq!(webhook.original_message_id, None);
+        untrack_repost(514).await.unwrap();
+    }
+}
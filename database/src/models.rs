@@ -1,7 +1,10 @@
 use crate::schema::{
-    beatmapset_subscriptions, beatmapsets, osu_user_group_gamemodes, osu_user_groups, osu_users,
-    sticky_messages, subscriptions,
+    beatmapset_subscriptions, beatmapsets, link_fix_stats, link_guild_settings,
+    link_provider_settings, link_reposts, link_rules, link_video_channels, link_webhooks,
+    osu_user_group_gamemodes, osu_user_groups, osu_users, sticky_messages, subscriptions,
+    user_settings,
 };
+use chrono::NaiveDate;
 use diesel::{
     AsExpression, Associations, FromSqlRow, Identifiable, Insertable, Queryable, Selectable,
     deserialize::{self, FromSql},
@@ -44,6 +47,50 @@ impl FromSql<crate::schema::sql_types::ChannelKind, Pg> for ChannelKind {
     }
 }
 
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, AsExpression, FromSqlRow)]
+#[diesel(sql_type = crate::schema::sql_types::LinkFixMode)]
+pub enum LinkFixMode {
+    /// The bot reposts the fixed message as itself, replying to the original context
+    #[default]
+    Reply,
+    /// The bot reposts the fixed message through a webhook using the authors name and avatar
+    Webhook,
+    /// The original message is kept with its embeds suppressed, the bot replies with the fixed links
+    Suppress,
+}
+
+impl ToSql<crate::schema::sql_types::LinkFixMode, Pg> for LinkFixMode {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        match *self {
+            LinkFixMode::Reply => out.write_all(b"reply")?,
+            LinkFixMode::Webhook => out.write_all(b"webhook")?,
+            LinkFixMode::Suppress => out.write_all(b"suppress")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::LinkFixMode, Pg> for LinkFixMode {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"reply" => Ok(LinkFixMode::Reply),
+            b"webhook" => Ok(LinkFixMode::Webhook),
+            b"suppress" => Ok(LinkFixMode::Suppress),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+impl Display for LinkFixMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            LinkFixMode::Reply => write!(f, "Reply"),
+            LinkFixMode::Webhook => write!(f, "Webhook"),
+            LinkFixMode::Suppress => write!(f, "Suppress"),
+        }
+    }
+}
+
 // TODO
 // Remove the "Alumni" group and create a `NonTracked` enum variant to future proof
 // any future groups being added to the osu api
@@ -248,6 +295,110 @@ pub struct NewStickyMessage {
     pub bot_message_id: i64,
 }
 
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = link_rules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkRules {
+    pub id: i32,
+    pub guild_id: Option<i64>,
+    pub pattern: String,
+    pub replacement: String,
+    pub builtin: bool,
+    pub provider: String,
+    /// Replacements to try in order when the mirror in `replacement` is down
+    pub fallbacks: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = link_rules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug)]
+pub struct NewLinkRule {
+    pub guild_id: Option<i64>,
+    pub pattern: String,
+    pub replacement: String,
+    pub builtin: bool,
+    pub provider: String,
+    /// Replacements to try in order when the mirror in `replacement` is down
+    pub fallbacks: Vec<String>,
+}
+
+/// A `None` channel applies to the whole guild and a `None` provider applies to every provider
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = link_provider_settings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkProviderSettings {
+    pub id: i32,
+    pub guild_id: i64,
+    pub channel_id: Option<i64>,
+    pub provider: Option<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = link_guild_settings)]
+#[diesel(primary_key(guild_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkGuildSettings {
+    pub guild_id: i64,
+    pub mode: LinkFixMode,
+}
+
+#[derive(Debug, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = link_webhooks)]
+#[diesel(primary_key(channel_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkWebhooks {
+    pub channel_id: i64,
+    pub webhook_url: String,
+}
+
+/// A channel where videos behind fixed links are downloaded and attached
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = link_video_channels)]
+#[diesel(primary_key(channel_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkVideoChannels {
+    pub channel_id: i64,
+    pub guild_id: i64,
+}
+
+#[derive(Debug, Queryable, Selectable, Identifiable, Insertable)]
+#[diesel(table_name = link_reposts)]
+#[diesel(primary_key(bot_message_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkReposts {
+    pub bot_message_id: i64,
+    pub channel_id: i64,
+    pub owner_id: i64,
+    /// `None` when the repost was tracked before originals were recorded
+    pub original_message_id: Option<i64>,
+    /// How the repost was sent, decides how it gets edited or deleted
+    pub mode: LinkFixMode,
+}
+
+/// How many links were fixed for one poster, in one channel, for one provider on one day
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = link_fix_stats)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkFixStats {
+    pub guild_id: i64,
+    pub channel_id: i64,
+    pub provider: String,
+    pub user_id: i64,
+    pub day: NaiveDate,
+    pub count: i32,
+}
+
+#[derive(Debug, Default, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = user_settings)]
+#[diesel(primary_key(user_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserSettings {
+    pub user_id: i64,
+    pub links_opt_out: bool,
+}
+
 #[derive(Queryable, Selectable, Identifiable, Insertable)]
 #[diesel(table_name = osu_users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -5,6 +5,10 @@ pub mod sql_types {
     #[diesel(postgres_type(name = "channel_kind"))]
     pub struct ChannelKind;
 
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "link_fix_mode"))]
+    pub struct LinkFixMode;
+
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "osu_gamemode"))]
     pub struct OsuGamemode;
@@ -28,6 +32,76 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    link_fix_stats (guild_id, channel_id, provider, user_id, day) {
+        guild_id -> Int8,
+        channel_id -> Int8,
+        provider -> Text,
+        user_id -> Int8,
+        day -> Date,
+        count -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LinkFixMode;
+
+    link_guild_settings (guild_id) {
+        guild_id -> Int8,
+        mode -> LinkFixMode,
+    }
+}
+
+diesel::table! {
+    link_provider_settings (id) {
+        id -> Int4,
+        guild_id -> Int8,
+        channel_id -> Nullable<Int8>,
+        provider -> Nullable<Text>,
+        enabled -> Bool,
+    }
+}
+
+diesel::table! {
+    link_rules (id) {
+        id -> Int4,
+        guild_id -> Nullable<Int8>,
+        pattern -> Text,
+        replacement -> Text,
+        builtin -> Bool,
+        provider -> Text,
+        fallbacks -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LinkFixMode;
+
+    link_reposts (bot_message_id) {
+        bot_message_id -> Int8,
+        channel_id -> Int8,
+        owner_id -> Int8,
+        original_message_id -> Nullable<Int8>,
+        mode -> LinkFixMode,
+    }
+}
+
+diesel::table! {
+    link_video_channels (channel_id) {
+        channel_id -> Int8,
+        guild_id -> Int8,
+    }
+}
+
+diesel::table! {
+    link_webhooks (channel_id) {
+        channel_id -> Int8,
+        webhook_url -> Text,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::OsuGamemode;
@@ -77,6 +151,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_settings (user_id) {
+        user_id -> Int8,
+        links_opt_out -> Bool,
+    }
+}
+
 diesel::joinable!(beatmapset_subscriptions -> beatmapsets (beatmapset_id));
 diesel::joinable!(osu_user_group_gamemodes -> osu_user_groups (user_group_id));
 diesel::joinable!(osu_user_groups -> osu_users (user_id));
@@ -84,9 +165,17 @@ diesel::joinable!(osu_user_groups -> osu_users (user_id));
 diesel::allow_tables_to_appear_in_same_query!(
     beatmapset_subscriptions,
     beatmapsets,
+    link_fix_stats,
+    link_guild_settings,
+    link_provider_settings,
+    link_reposts,
+    link_rules,
+    link_video_channels,
+    link_webhooks,
     osu_user_group_gamemodes,
     osu_user_groups,
     osu_users,
     sticky_messages,
     subscriptions,
+    user_settings,
 );
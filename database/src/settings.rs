@@ -0,0 +1,74 @@
+use crate::{
+    core::{DB, macros::get_conn},
+    models::UserSettings,
+    schema::{self, user_settings::dsl::user_settings},
+};
+use anyhow::Result;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper, upsert::excluded};
+use diesel_async::RunQueryDsl;
+use tracing::{debug, instrument};
+
+/// Fetches a users settings, users without a row get the defaults
+pub async fn fetch_user_settings(user_id: i64) -> Result<UserSettings> {
+    let settings = user_settings
+        .filter(schema::user_settings::user_id.eq(user_id))
+        .select(UserSettings::as_select())
+        .first(get_conn!())
+        .await
+        .optional()?;
+
+    Ok(settings.unwrap_or(UserSettings {
+        user_id,
+        ..Default::default()
+    }))
+}
+
+pub async fn fetch_all_opted_out_users() -> Result<Vec<i64>> {
+    let ids = user_settings
+        .filter(schema::user_settings::links_opt_out.eq(true))
+        .select(schema::user_settings::user_id)
+        .load(get_conn!())
+        .await?;
+
+    Ok(ids)
+}
+
+#[instrument]
+pub async fn set_links_opt_out(user_id: i64, opt_out: bool) -> Result<()> {
+    diesel::insert_into(user_settings)
+        .values((
+            schema::user_settings::user_id.eq(user_id),
+            schema::user_settings::links_opt_out.eq(opt_out),
+        ))
+        .on_conflict(schema::user_settings::user_id)
+        .do_update()
+        .set(
+            schema::user_settings::links_opt_out.eq(excluded(schema::user_settings::links_opt_out)),
+        )
+        .execute(get_conn!())
+        .await?;
+    debug!("Upserted");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::init_db;
+
+    #[tokio::test]
+    async fn toggle_opt_out() {
+        init_db().await;
+
+        assert!(!fetch_user_settings(300).await.unwrap().links_opt_out);
+
+        set_links_opt_out(300, true).await.unwrap();
+        assert!(fetch_user_settings(300).await.unwrap().links_opt_out);
+        assert!(fetch_all_opted_out_users().await.unwrap().contains(&300));
+
+        set_links_opt_out(300, false).await.unwrap();
+        assert!(!fetch_user_settings(300).await.unwrap().links_opt_out);
+        assert!(!fetch_all_opted_out_users().await.unwrap().contains(&300));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    core::{DB, macros::get_conn},
+    models::LinkFixStats,
+    schema::{self, link_fix_stats::dsl::link_fix_stats},
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper, upsert::excluded};
+use diesel_async::RunQueryDsl;
+use tracing::{debug, instrument};
+
+/// Adds fixed link counts to todays rows, rows that already exist are bumped
+///
+/// Rows are kept per poster, the stats command ranks the top posters from them
+#[instrument(skip(stats))]
+pub async fn record_link_fixes(stats: Vec<LinkFixStats>) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    diesel::insert_into(link_fix_stats)
+        .values(stats)
+        .on_conflict((
+            schema::link_fix_stats::guild_id,
+            schema::link_fix_stats::channel_id,
+            schema::link_fix_stats::provider,
+            schema::link_fix_stats::user_id,
+            schema::link_fix_stats::day,
+        ))
+        .do_update()
+        .set(
+            schema::link_fix_stats::count
+                .eq(schema::link_fix_stats::count + excluded(schema::link_fix_stats::count)),
+        )
+        .execute(get_conn!())
+        .await?;
+    debug!("Upserted");
+
+    Ok(())
+}
+
+/// Every row for a guild since `since`, oldest first
+pub async fn fetch_link_stats(guild_id: i64, since: NaiveDate) -> Result<Vec<LinkFixStats>> {
+    let stats = link_fix_stats
+        .filter(schema::link_fix_stats::guild_id.eq(guild_id))
+        .filter(schema::link_fix_stats::day.ge(since))
+        .order((
+            schema::link_fix_stats::day.asc(),
+            schema::link_fix_stats::channel_id.asc(),
+            schema::link_fix_stats::provider.asc(),
+            schema::link_fix_stats::user_id.asc(),
+        ))
+        .select(LinkFixStats::as_select())
+        .load(get_conn!())
+        .await?;
+
+    Ok(stats)
+}
+
+/// Deletes every row older than `before`, returns how many were removed
+#[instrument]
+pub async fn prune_link_stats(before: NaiveDate) -> Result<usize> {
+    let deleted = diesel::delete(link_fix_stats)
+        .filter(schema::link_fix_stats::day.lt(before))
+        .execute(get_conn!())
+        .await?;
+    debug!("Deleted");
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::init_db;
+    use pretty_assertions::assert_eq;
+
+    fn row(
+        guild_id: i64,
+        provider: &str,
+        user_id: i64,
+        day: NaiveDate,
+        count: i32,
+    ) -> LinkFixStats {
+        LinkFixStats {
+            guild_id,
+            channel_id: 1,
+            provider: provider.to_string(),
+            user_id,
+            day,
+            count,
+        }
+    }
+
+    /// Removes rows left over from an earlier run, leaving every other guild alone
+    async fn clear_guild(guild_id: i64) {
+        diesel::delete(link_fix_stats)
+            .filter(schema::link_fix_stats::guild_id.eq(guild_id))
+            .execute(get_conn!())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn aggregates_link_fixes() {
+        init_db().await;
+
+        let guild_id = 7_000_001;
+        // Long before any real stats, so pruning only reaches rows made here
+        let today = NaiveDate::from_ymd_opt(1000, 3, 25).unwrap();
+        let old = NaiveDate::from_ymd_opt(999, 1, 1).unwrap();
+        clear_guild(guild_id).await;
+
+        record_link_fixes(vec![
+            row(guild_id, "twitter", 1, today, 2),
+            row(guild_id, "pixiv", 2, today, 1),
+        ])
+        .await
+        .unwrap();
+        record_link_fixes(vec![
+            row(guild_id, "twitter", 1, today, 1),
+            row(guild_id, "pixiv", 1, old, 5),
+        ])
+        .await
+        .unwrap();
+
+        let stats = fetch_link_stats(guild_id, old).await.unwrap();
+        assert_eq!(
+            stats
+                .iter()
+                .map(|s| (s.provider.as_str(), s.user_id, s.count))
+                .collect::<Vec<_>>(),
+            vec![("pixiv", 1, 5), ("pixiv", 2, 1), ("twitter", 1, 3)]
+        );
+        assert_eq!(fetch_link_stats(guild_id, today).await.unwrap().len(), 2);
+
+        assert_eq!(prune_link_stats(today).await.unwrap(), 1);
+        assert_eq!(fetch_link_stats(guild_id, old).await.unwrap().len(), 2);
+    }
+}
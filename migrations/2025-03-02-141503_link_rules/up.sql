@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE link_rules
+(
+    id          SERIAL PRIMARY KEY,
+    guild_id    BIGINT,
+    pattern     TEXT    NOT NULL,
+    replacement TEXT    NOT NULL,
+    builtin     BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX link_rules_guild_id_idx ON link_rules (guild_id);
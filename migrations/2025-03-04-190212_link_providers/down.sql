@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE link_provider_settings;
+
+ALTER TABLE link_rules
+    DROP COLUMN provider;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE link_rules
+    ADD COLUMN provider TEXT NOT NULL DEFAULT 'custom';
+
+-- A NULL `channel_id` applies to the whole guild and a NULL `provider` applies to every provider
+CREATE TABLE link_provider_settings
+(
+    id         SERIAL PRIMARY KEY,
+    guild_id   BIGINT  NOT NULL,
+    channel_id BIGINT,
+    provider   TEXT,
+    enabled    BOOLEAN NOT NULL,
+    UNIQUE NULLS NOT DISTINCT (guild_id, channel_id, provider)
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE user_settings
+(
+    user_id       BIGINT PRIMARY KEY,
+    links_opt_out BOOLEAN NOT NULL DEFAULT FALSE
+);
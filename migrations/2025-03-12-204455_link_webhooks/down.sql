@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE link_reposts;
+DROP TABLE link_webhooks;
+DROP TABLE link_guild_settings;
+DROP TYPE link_fix_mode;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TYPE link_fix_mode AS ENUM ('reply', 'webhook');
+
+CREATE TABLE link_guild_settings
+(
+    guild_id BIGINT PRIMARY KEY,
+    mode     link_fix_mode NOT NULL DEFAULT 'reply'
+);
+
+CREATE TABLE link_webhooks
+(
+    channel_id  BIGINT PRIMARY KEY,
+    webhook_url TEXT NOT NULL
+);
+
+CREATE TABLE link_reposts
+(
+    bot_message_id BIGINT PRIMARY KEY,
+    channel_id     BIGINT NOT NULL,
+    owner_id       BIGINT NOT NULL
+);
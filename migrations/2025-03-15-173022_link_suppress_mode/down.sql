@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+UPDATE link_guild_settings
+SET mode = 'reply'
+WHERE mode = 'suppress';
+
+ALTER TYPE link_fix_mode RENAME TO link_fix_mode_old;
+CREATE TYPE link_fix_mode AS ENUM ('reply', 'webhook');
+
+ALTER TABLE link_guild_settings
+    ALTER COLUMN mode DROP DEFAULT,
+    ALTER COLUMN mode TYPE link_fix_mode USING mode::text::link_fix_mode,
+    ALTER COLUMN mode SET DEFAULT 'reply';
+
+DROP TYPE link_fix_mode_old;
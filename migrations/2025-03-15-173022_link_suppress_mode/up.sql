@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TYPE link_fix_mode ADD VALUE 'suppress';
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE link_rules
+    ADD COLUMN fallbacks TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX link_reposts_original_message_id_idx;
+
+ALTER TABLE link_reposts
+    DROP COLUMN mode,
+    DROP COLUMN original_message_id;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- Every repost tracked before this was sent through a webhook
+ALTER TABLE link_reposts
+    ADD COLUMN original_message_id BIGINT,
+    ADD COLUMN mode                link_fix_mode NOT NULL DEFAULT 'webhook';
+
+CREATE INDEX link_reposts_original_message_id_idx ON link_reposts (original_message_id);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- One row per guild, channel, provider, poster and day, bumped on every fix
+-- The poster is part of the key so /stats links can rank the top posters
+CREATE TABLE link_fix_stats
+(
+    guild_id   BIGINT  NOT NULL,
+    channel_id BIGINT  NOT NULL,
+    provider   TEXT    NOT NULL,
+    user_id    BIGINT  NOT NULL,
+    day        DATE    NOT NULL,
+    count      INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, channel_id, provider, user_id, day)
+);
+
+CREATE INDEX link_fix_stats_guild_id_day_idx ON link_fix_stats (guild_id, day);
+CREATE INDEX link_fix_stats_day_idx ON link_fix_stats (day);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE link_video_channels
+(
+    channel_id BIGINT PRIMARY KEY,
+    guild_id   BIGINT NOT NULL
+);